@@ -0,0 +1,159 @@
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Content-addressed store of downloaded files, keyed by their sha256 digest
+#[derive(Clone, Debug)]
+pub struct BlobCache {
+    pub root: PathBuf,
+}
+
+pub struct CacheEntry {
+    pub sha256: String,
+    pub size: u64,
+    pub last_used: SystemTime,
+}
+
+impl BlobCache {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// The cache under the user's cache directory, e.g. `~/.cache/decky/blobs`.
+    pub fn open_default() -> Result<Self> {
+        dirs::cache_dir()
+            .map(|dir| BlobCache::new(dir.join("decky").join("blobs")))
+            .ok_or_else(|| anyhow!("Could not determine the user cache directory"))
+    }
+
+    fn is_digest(name: &str) -> bool {
+        name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit())
+    }
+
+    pub fn path_for(&self, sha256: &str) -> PathBuf {
+        self.root.join(sha256.to_ascii_lowercase())
+    }
+
+    pub fn hash_file(path: &Path) -> Result<String> {
+        let mut file = fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Returns the cached blob for `sha256`, if present and intact. Corrupted
+    /// blobs are evicted so they get downloaded again.
+    pub fn get(&self, sha256: &str) -> Result<Option<PathBuf>> {
+        let path = self.path_for(sha256);
+
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        if BlobCache::hash_file(&path)? != sha256.to_ascii_lowercase() {
+            warn!("Cached blob {} is corrupted, removing it", sha256);
+            fs::remove_file(&path)?;
+            return Ok(None);
+        }
+
+        // Used by `prune` to tell which blobs are still in use
+        fs::File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+            .ok();
+
+        Ok(Some(path))
+    }
+
+    /// Copies a cached blob to `destination`. Returns false on a cache miss.
+    pub fn copy_to(&self, sha256: &str, destination: &Path) -> Result<bool> {
+        match self.get(sha256)? {
+            Some(blob) => {
+                if let Some(parent) = destination.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(&blob, destination).with_context(|| {
+                    format!("Failed to copy cached blob to {}", destination.display())
+                })?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn entries(&self) -> Result<Vec<CacheEntry>> {
+        if !self.root.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut entries = vec![];
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            if !BlobCache::is_digest(&name) {
+                continue;
+            }
+
+            let metadata = entry.metadata()?;
+            entries.push(CacheEntry {
+                sha256: name,
+                size: metadata.len(),
+                last_used: metadata.modified()?,
+            });
+        }
+
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.last_used));
+        Ok(entries)
+    }
+
+    /// Removes everything in the cache. Returns the number of blobs removed.
+    pub fn clean(&self) -> Result<usize> {
+        let count = self.entries()?.len();
+
+        if self.root.exists() {
+            fs::remove_dir_all(&self.root)
+                .with_context(|| format!("Failed to remove {}", self.root.display()))?;
+        }
+
+        Ok(count)
+    }
+
+    /// Removes blobs that have not been used for `max_age`, along with any
+    /// leftovers from interrupted downloads.
+    pub fn prune(&self, max_age: Duration) -> Result<usize> {
+        if !self.root.exists() {
+            return Ok(0);
+        }
+
+        let now = SystemTime::now();
+        let mut removed = 0;
+
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let age = now
+                .duration_since(entry.metadata()?.modified()?)
+                .unwrap_or_default();
+
+            let stale = match BlobCache::is_digest(&name) {
+                true => age > max_age,
+                false => name.ends_with(".part") && age > Duration::from_secs(3600),
+            };
+
+            if stale {
+                info!("Removing {}", name);
+                fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
@@ -0,0 +1,53 @@
+use super::{CacheCLI, CacheCommand};
+use anyhow::Result;
+use log::info;
+use std::time::{Duration, SystemTime};
+
+use crate::{cache::BlobCache, util::format_size};
+
+fn list(cache: &BlobCache) -> Result<()> {
+    let entries = cache.entries()?;
+    let now = SystemTime::now();
+
+    for entry in &entries {
+        let days = now
+            .duration_since(entry.last_used)
+            .unwrap_or_default()
+            .as_secs()
+            / 86400;
+
+        println!(
+            "{}  {:>10}  last used {} day(s) ago",
+            entry.sha256,
+            format_size(entry.size),
+            days
+        );
+    }
+
+    println!(
+        "{} blob(s), {} in {}",
+        entries.len(),
+        format_size(entries.iter().map(|entry| entry.size).sum()),
+        cache.root.display()
+    );
+
+    Ok(())
+}
+
+pub fn parse(args: &CacheCLI) -> Result<()> {
+    let cache = BlobCache::open_default()?;
+
+    match &args.command {
+        CacheCommand::List => list(&cache),
+        CacheCommand::Clean => {
+            let removed = cache.clean()?;
+            info!("Removed {} blob(s) from {}", removed, cache.root.display());
+            Ok(())
+        }
+        CacheCommand::Prune { days } => {
+            let removed = cache.prune(Duration::from_secs(days * 86400))?;
+            info!("Removed {} file(s) from {}", removed, cache.root.display());
+            Ok(())
+        }
+    }
+}
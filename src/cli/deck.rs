@@ -0,0 +1,75 @@
+use super::{DeckCLI, DeckCommand};
+use anyhow::{anyhow, Result};
+use log::info;
+
+use crate::config::{DeckProfile, DeckProfiles};
+
+fn list(profiles: &DeckProfiles) {
+    for (name, deck) in &profiles.deck {
+        println!(
+            "{}  {}@{}:{}  key: {}  dir: {}",
+            name,
+            deck.user.as_deref().unwrap_or("deck"),
+            deck.ip.as_deref().unwrap_or("?"),
+            deck.port.unwrap_or(22),
+            deck.key
+                .as_ref()
+                .map(|key| key.display().to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            deck.dir.as_deref().unwrap_or("/home/deck"),
+        );
+    }
+
+    println!("{} deck(s)", profiles.deck.len());
+}
+
+pub fn parse(args: &DeckCLI) -> Result<()> {
+    let location = DeckProfiles::default_location()?;
+    let mut profiles = DeckProfiles::load(&location)?;
+
+    match &args.command {
+        DeckCommand::Add {
+            name,
+            ip,
+            port,
+            user,
+            key,
+            dir,
+        } => {
+            let replaced = profiles.deck.insert(
+                name.clone(),
+                DeckProfile {
+                    ip: Some(ip.clone()),
+                    port: *port,
+                    user: user.clone(),
+                    key: key.clone(),
+                    dir: dir.clone(),
+                },
+            );
+            profiles.save(&location)?;
+            info!(
+                "{} {} in {}",
+                match replaced {
+                    Some(_) => "Replaced",
+                    None => "Added",
+                },
+                name,
+                location.display()
+            );
+            Ok(())
+        }
+        DeckCommand::List => {
+            list(&profiles);
+            Ok(())
+        }
+        DeckCommand::Remove { name } => {
+            profiles
+                .deck
+                .remove(name)
+                .ok_or_else(|| anyhow!("No Deck named `{}`", name))?;
+            profiles.save(&location)?;
+            info!("Removed {} from {}", name, location.display());
+            Ok(())
+        }
+    }
+}
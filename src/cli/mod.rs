@@ -1,31 +1,81 @@
+pub mod cache;
+pub mod deck;
 pub mod plugin;
+pub mod prompt;
 
 use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
 
+use crate::plugin::Flag;
+use crate::util::parse_size;
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[allow(clippy::upper_case_acronyms)]
 pub struct CLI {
     #[command(subcommand)]
     pub command: Command,
 }
 
-#[derive(clap::ValueEnum, Clone)]
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq, Eq)]
 pub enum ContainerEngine {
+    /// Use the first of docker, podman and nerdctl that works
+    Auto,
     Docker,
     Podman,
+    /// containerd's nerdctl, including Lima's `nerdctl.lima`
+    Nerdctl,
+    /// Build the frontend on the host with pnpm or npm, without containers
+    Native,
 }
 
 impl ContainerEngine {
+    /// Engines tried by `auto`, in order of preference
+    pub const DETECTABLE: [ContainerEngine; 3] = [Self::Docker, Self::Podman, Self::Nerdctl];
+
     pub fn bin_name(&self) -> &str {
         match self {
+            Self::Auto => "auto",
             Self::Docker => "docker",
             Self::Podman => "podman",
+            Self::Nerdctl => "nerdctl",
+            Self::Native => "native",
+        }
+    }
+
+    /// Names the engine's command line client may be installed under
+    pub fn cli_names(&self) -> &[&'static str] {
+        match self {
+            Self::Nerdctl => &["nerdctl", "nerdctl.lima"],
+            Self::Docker => &["docker"],
+            Self::Podman => &["podman"],
+            Self::Auto | Self::Native => &[],
         }
     }
+
+    pub fn uses_containers(&self) -> bool {
+        !matches!(self, Self::Native)
+    }
+}
+
+#[derive(clap::ValueEnum, Clone)]
+pub enum EngineTransport {
+    /// Use the API socket if it responds, otherwise the command line client
+    Auto,
+    /// Talk to the engine's Docker-compatible API socket
+    Socket,
+    /// Run the engine's command line client
+    Cli,
 }
 
+#[derive(clap::ValueEnum, Clone)]
+pub enum DeployTransport {
+    /// Talk SSH and SFTP to the Deck directly
+    Native,
+    /// Run the ssh and rsync programs installed on this machine
+    Openssh,
+}
 
 #[derive(clap::ValueEnum, Clone)]
 pub enum CompressMethod {
@@ -33,9 +83,67 @@ pub enum CompressMethod {
     Store,
 }
 
+#[derive(clap::ValueEnum, Clone)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub enum Command {
     Plugin(PluginCLI),
+    Cache(CacheCLI),
+    Deck(DeckCLI),
+}
+
+#[derive(Parser)]
+pub struct CacheCLI {
+    #[command(subcommand)]
+    command: CacheCommand,
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    List,
+    Clean,
+    Prune {
+        #[arg(short, long, default_value = "30")]
+        days: u64,
+    },
+}
+
+#[derive(Parser)]
+pub struct DeckCLI {
+    #[command(subcommand)]
+    command: DeckCommand,
+}
+
+#[derive(Subcommand)]
+pub enum DeckCommand {
+    /// Save a Deck to deploy to with `--deck <name>`, replacing any with the same name
+    Add {
+        name: String,
+
+        #[arg(short, long)]
+        ip: String,
+
+        #[arg(short, long)]
+        port: Option<u16>,
+
+        #[arg(short, long)]
+        user: Option<String>,
+
+        #[arg(short, long)]
+        key: Option<PathBuf>,
+
+        #[arg(short, long)]
+        dir: Option<String>,
+    },
+    List,
+    Remove {
+        name: String,
+    },
 }
 
 #[derive(Parser)]
@@ -74,16 +182,63 @@ pub enum PluginCommand {
         #[arg(short = 's', long, value_enum, default_value = "plugin-name")]
         output_filename_source: FilenameSource,
 
-        #[arg(short = 'e', long = "engine", default_value = "docker")]
+        #[arg(short = 'e', long = "engine", default_value = "auto")]
         container_engine: ContainerEngine,
 
+        #[arg(long, value_enum, default_value = "auto")]
+        engine_transport: EngineTransport,
+
         #[arg(short = 'm', long, default_value = "deflate")]
         compression_method: CompressMethod,
 
         #[arg(short = 'l', long)]
         compression_level: Option<i32>,
+
+        #[arg(long, default_value = "false")]
+        offline: bool,
+
+        #[arg(long)]
+        binaries_dir: Option<PathBuf>,
+
+        #[arg(long)]
+        builder_image: Option<String>,
+
+        #[arg(long, default_value = "false")]
+        force: bool,
+
+        #[arg(short, long, default_value = "4")]
+        jobs: usize,
+
+        #[arg(long, action = clap::ArgAction::Set, default_value = "true")]
+        reproducible: bool,
+
+        #[arg(long, value_parser = parse_size)]
+        max_size: Option<u64>,
+
+        #[arg(short, long, default_value = "false")]
+        quiet: bool,
+
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+    },
+    New {
+        plugin_path: Option<PathBuf>,
+
+        #[arg(short, long)]
+        name: Option<String>,
+
+        #[arg(short, long)]
+        author: Option<String>,
+
+        #[arg(short = 'f', long = "flag")]
+        flags: Vec<Flag>,
+
+        #[arg(short, long, default_value = "false")]
+        backend: bool,
+
+        #[arg(short, long, default_value = "false")]
+        py_modules: bool,
     },
-    New,
     Deploy {
         #[arg(default_value = "./")]
         plugin_path: PathBuf,
@@ -103,15 +258,45 @@ pub enum PluginCommand {
         #[arg(short = 's', long, value_enum, default_value = "plugin-name")]
         output_filename_source: FilenameSource,
 
-        #[arg(short = 'e', long = "engine", default_value = "docker")]
+        #[arg(short = 'e', long = "engine", default_value = "auto")]
         container_engine: ContainerEngine,
 
+        #[arg(long, value_enum, default_value = "auto")]
+        engine_transport: EngineTransport,
+
         #[arg(short = 'm', long, default_value = "deflate")]
         compression_method: CompressMethod,
 
         #[arg(short = 'l', long)]
         compression_level: Option<i32>,
 
+        #[arg(long, default_value = "false")]
+        offline: bool,
+
+        #[arg(long)]
+        binaries_dir: Option<PathBuf>,
+
+        #[arg(long)]
+        builder_image: Option<String>,
+
+        #[arg(long, default_value = "false")]
+        force: bool,
+
+        #[arg(short, long, default_value = "4")]
+        jobs: usize,
+
+        #[arg(long, action = clap::ArgAction::Set, default_value = "true")]
+        reproducible: bool,
+
+        #[arg(long, value_parser = parse_size)]
+        max_size: Option<u64>,
+
+        #[arg(short, long, default_value = "false")]
+        quiet: bool,
+
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+
         #[arg(short = 'S', long, default_value = "true")]
         follow_symlinks: bool,
 
@@ -124,10 +309,34 @@ pub enum PluginCommand {
         #[arg(short = 'x', long)]
         deck_pass: Option<String>,
 
+        /// Private key to log in with, as a path or as ssh's `-i <path>`
         #[arg(short = 'k', long)]
         deck_key: Option<String>,
 
         #[arg(short = 'c', long)]
         deck_dir: Option<String>,
+
+        #[arg(long = "deck")]
+        deck_profile: Option<String>,
+
+        #[arg(long, value_enum, default_value = "native")]
+        transport: DeployTransport,
+
+        #[arg(long, default_value = "false")]
+        no_sudo: bool,
+    },
+    Validate {
+        #[arg(default_value = "./")]
+        plugin_path: PathBuf,
+
+        #[arg(short, long, value_enum, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// List the contents of a built plugin zip and check it for problems
+    Inspect {
+        zip_path: PathBuf,
+
+        #[arg(short, long, value_enum, default_value = "text")]
+        format: OutputFormat,
     },
 }
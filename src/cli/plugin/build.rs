@@ -1,30 +1,203 @@
 use anyhow::{anyhow, Context, Result};
 use boolinator::Boolinator;
+use futures::future::join_all;
 use glob::glob;
 use itertools::Itertools;
-use log::{error, info};
-use rand::distributions::{Alphanumeric, DistString};
-use serde_json::Value;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::{
+    collections::HashSet,
+    ffi::OsString,
     fs,
     fs::File,
     io::Write,
     os,
     path::{Path, PathBuf},
+    sync::Arc,
 };
+use time::OffsetDateTime;
+use tokio::sync::Semaphore;
 use walkdir::WalkDir;
-use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+use zip::{write::FileOptions, CompressionMethod, DateTime, ZipWriter};
 
 use crate::{
-    cli::{CompressMethod, ContainerEngine, FilenameSource},
-    container_engine,
-    plugin::{CustomBackend, Plugin},
+    cache::BlobCache,
+    cli::{CompressMethod, ContainerEngine, EngineTransport, FilenameSource},
+    config::ZipRules,
+    container_engine::{self, Engine, RunOptions},
+    fingerprint::{Fingerprint, Fingerprints},
+    output::{BuildOutput, StageLog},
+    plugin::{CustomBackend, Flag, Plugin},
+    remote_binary::{Downloader, RemoteBinaries},
+    util::{format_size, parse_size},
 };
 
+/// Files from the plugin root that end up in the zip
+const PLUGIN_FILES: [&str; 5] = [
+    "LICENSE",
+    "main.py",
+    "package.json",
+    "plugin.json",
+    "README.md",
+];
+
+pub const DEFAULT_BUILDER_IMAGE: &str = "ghcr.io/steamdeckhomebrew/builder:latest";
+
+/// Where stage fingerprints are kept inside the build directory
+const FINGERPRINTS_DIR: &str = ".fingerprints";
+
+/// Where remote binaries are downloaded to before they are installed into
+/// `bin/`, which the backend writes to at the same time
+const REMOTE_BINARIES_DIR: &str = ".remote-binaries";
+
+/// Parts of the build that are skipped when their inputs did not change
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    Backend,
+    Frontend,
+    RemoteBinaries,
+    PyModules,
+}
+
+impl Stage {
+    /// Every stage, in the order they run
+    const ALL: [Stage; 4] = [
+        Stage::Backend,
+        Stage::Frontend,
+        Stage::RemoteBinaries,
+        Stage::PyModules,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Stage::Backend => "backend",
+            Stage::Frontend => "frontend",
+            Stage::RemoteBinaries => "remote-binaries",
+            Stage::PyModules => "py-modules",
+        }
+    }
+
+    /// Whether both stages write to the same directory, so they have to be
+    /// rebuilt together
+    fn shares_output(&self, other: &Stage) -> bool {
+        let writes_bin = |stage: &Stage| matches!(stage, Stage::Backend | Stage::RemoteBinaries);
+        self == other || (writes_bin(self) && writes_bin(other))
+    }
+}
+
+/// A file and its sha256 digest
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ManifestFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Describes what went into a zip and how it was built, written next to it so
+/// releases can be audited
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Manifest {
+    pub name: String,
+    /// `version` from package.json
+    pub version: Option<String>,
+    /// `production` or `development`
+    pub mode: String,
+    pub cli_version: String,
+    /// None when built without containers
+    pub builder_image: Option<String>,
+    pub builder_image_digest: Option<String>,
+    pub zip: ManifestFile,
+    pub files: Vec<ManifestFile>,
+}
+
+/// A plugin zip being written
+struct PluginZip {
+    writer: ZipWriter<File>,
+    /// Names already in the zip, relative to the plugin's directory, so
+    /// included files can't add them twice
+    names: HashSet<PathBuf>,
+    /// Uncompressed size of every file in the zip
+    sizes: Vec<(PathBuf, u64)>,
+}
+
+/// How big a zip is and what takes up the space in it
+pub struct ZipReport {
+    pub files: Vec<(PathBuf, u64)>,
+    /// Size of the zip itself
+    pub zip_size: u64,
+}
+
+impl ZipReport {
+    /// How many of the largest files and directories are listed
+    const LARGEST: usize = 5;
+
+    pub fn uncompressed_size(&self) -> u64 {
+        self.files.iter().map(|(_, size)| size).sum()
+    }
+
+    fn largest(entries: impl IntoIterator<Item = (PathBuf, u64)>) -> Vec<(PathBuf, u64)> {
+        entries
+            .into_iter()
+            .sorted_by(|(a_name, a_size), (b_name, b_size)| {
+                b_size.cmp(a_size).then(a_name.cmp(b_name))
+            })
+            .take(Self::LARGEST)
+            .collect()
+    }
+
+    pub fn largest_files(&self) -> Vec<(PathBuf, u64)> {
+        Self::largest(self.files.iter().cloned())
+    }
+
+    /// Directories with the most data in them, counting subdirectories
+    pub fn largest_dirs(&self) -> Vec<(PathBuf, u64)> {
+        let mut dirs = std::collections::BTreeMap::<PathBuf, u64>::new();
+        for (file, size) in &self.files {
+            for dir in file.ancestors().skip(1) {
+                if !dir.as_os_str().is_empty() {
+                    *dirs.entry(dir.to_path_buf()).or_default() += size;
+                }
+            }
+        }
+
+        Self::largest(dirs)
+    }
+
+    pub fn log(&self) {
+        info!(
+            "Zip is {} ({} uncompressed, {} file(s))",
+            format_size(self.zip_size),
+            format_size(self.uncompressed_size()),
+            self.files.len()
+        );
+
+        for (title, entries) in [
+            ("directories", self.largest_dirs()),
+            ("files", self.largest_files()),
+        ] {
+            if entries.is_empty() {
+                continue;
+            }
+
+            info!("Largest {}:", title);
+            for (name, size) in entries {
+                info!("  {:>10}  {}", format_size(size), name.display());
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Builder {
     docker_image: String,
+    builder_image_digest: Option<String>,
+    /// Image the custom backend is built in, once built
+    backend_image: Option<String>,
+    backend_image_digest: Option<String>,
+    engine: Arc<dyn Engine>,
+    output: BuildOutput,
+    zip_rules: ZipRules,
 
     pub plugin: Plugin,
     pub plugin_root: PathBuf,
@@ -37,147 +210,310 @@ pub struct Builder {
     pub container_engine: ContainerEngine,
     pub compression_method: CompressMethod,
     pub compression_level: Option<i32>,
+    pub offline: bool,
+    pub binaries_dir: Option<PathBuf>,
+    pub force: bool,
+    pub jobs: usize,
+    pub reproducible: bool,
+    /// Largest the zip may be, in bytes
+    pub max_size: Option<u64>,
+    /// Timestamp for zip entries in reproducible builds, from `SOURCE_DATE_EPOCH`
+    pub source_date_epoch: Option<i64>,
+    /// Where the native engine looks for pnpm and npm, `$PATH` if None
+    search_path: Option<OsString>,
 }
 
 impl Builder {
-    pub async fn build_frontend(&self) -> Result<()> {
-        info!("Building frontend");
+    /// Builds the frontend on the host, laying out `tmp_build_root` the same
+    /// way the builder image does.
+    async fn build_frontend_native(&self, log: &StageLog) -> Result<()> {
+        let package_manager =
+            container_engine::native_package_manager(self.search_path.as_deref())?;
+        info!(
+            "Building frontend natively with {}",
+            package_manager.display()
+        );
 
-        container_engine::run_image(
-            &self.container_engine,
-            self.docker_image.clone(),
-            vec![
-                (
-                    self.plugin_root.canonicalize()?.to_str().unwrap().into(),
-                    "/plugin".into(),
-                ),
-                (self.tmp_build_root.to_str().unwrap().into(), "/out".into()),
-            ],
-            self.build_as_root.clone(),
-            self.build_with_dev.clone(),
+        let release_type = match self.build_with_dev {
+            true => "development",
+            false => "production",
+        };
+
+        let mut install = vec!["install"];
+        if self.offline {
+            install.push("--offline");
+        }
+
+        for args in [install, vec!["run", "build"]] {
+            let mut cmd = tokio::process::Command::new(&package_manager);
+            cmd.args(args)
+                .current_dir(&self.plugin_root)
+                .env("RELEASE_TYPE", release_type);
+            container_engine::run_command(&mut cmd, log).await?;
+        }
+
+        self.copy_dir(
+            self.plugin_root.join("dist"),
+            self.tmp_build_root.join("dist"),
         )
-        .await
+        .context("Could not copy dist/, did the build script produce it?")?;
+
+        if self.plugin_root.join("defaults").exists() {
+            self.copy_dir(
+                self.plugin_root.join("defaults"),
+                self.tmp_build_root.join("defaults"),
+            )?;
+        }
+
+        let python_files = glob(&format!("{}/*.py", self.plugin_root.to_string_lossy()))?
+            .filter_map(|f| f.ok())
+            .filter_map(|f| f.file_name().map(|name| name.to_string_lossy().to_string()));
+
+        for file in PLUGIN_FILES
+            .into_iter()
+            .map(|f| f.to_string())
+            .chain(python_files)
+            .unique()
+        {
+            let source = self.plugin_root.join(&file);
+            if source.is_file() {
+                fs::copy(&source, self.tmp_build_root.join(&file))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn build_frontend(&self, log: &StageLog) -> Result<()> {
+        if !self.container_engine.uses_containers() {
+            return self.build_frontend_native(log).await;
+        }
+
+        info!("Building frontend");
+
+        self.engine
+            .run_image(
+                &RunOptions {
+                    image: self.docker_image.clone(),
+                    binds: vec![
+                        (
+                            self.plugin_root.canonicalize()?.to_str().unwrap().into(),
+                            "/plugin".into(),
+                        ),
+                        (self.tmp_build_root.to_str().unwrap().into(), "/out".into()),
+                    ],
+                    run_as_root: self.build_as_root,
+                    run_with_dev: self.build_with_dev,
+                    offline: self.offline,
+                },
+                log,
+            )
+            .await
     }
 
-    pub async fn build_backend(&self) -> Result<()> {
+    pub async fn build_backend(&self, log: &StageLog) -> Result<()> {
         if !&self.plugin_root.join("backend").exists() {
             info!("Plugin does not have a custom backend");
             return Ok(());
         }
 
+        if !self.container_engine.uses_containers() {
+            return Err(anyhow!(
+                "The native engine cannot build custom backends. Use a container engine like docker or podman instead."
+            ));
+        }
+
         info!("Building backend");
-        let mut image_tag: String = self.docker_image.clone();
+        let image = self
+            .backend_image
+            .clone()
+            .ok_or_else(|| anyhow!("The backend image has not been built"))?;
 
-        match self.plugin.custom_backend {
-            CustomBackend::Dockerfile => {
-                image_tag = container_engine::build_image(
-                    &self.container_engine,
-                    self.plugin_root.join("backend").join("Dockerfile"),
-                    self.plugin.meta.name.to_ascii_lowercase().replace(" ", "-"),
-                )
-                .await?
-                .clone();
+        self.engine
+            .run_image(
+                &RunOptions {
+                    image,
+                    binds: vec![
+                        (
+                            self.plugin_root
+                                .join("backend")
+                                .canonicalize()?
+                                .to_str()
+                                .unwrap()
+                                .into(),
+                            "/backend".into(),
+                        ),
+                        (
+                            self.tmp_build_root.join("bin").to_str().unwrap().into(),
+                            "/backend/out".into(),
+                        ),
+                        (
+                            self.plugin_root.canonicalize()?.to_str().unwrap().into(),
+                            "/plugin".into(),
+                        ),
+                    ],
+                    run_as_root: self.build_as_root,
+                    run_with_dev: self.build_with_dev,
+                    offline: self.offline,
+                },
+                log,
+            )
+            .await
+    }
+
+    fn downloader(&self) -> Downloader {
+        let mut downloader = match BlobCache::open_default() {
+            Ok(cache) => Downloader::with_cache(cache),
+            Err(err) => {
+                warn!("{:#}, remote binaries will not be cached", err);
+                Downloader::default()
+            }
+        };
+        downloader.binaries_dir = self.binaries_dir.clone();
+        downloader.offline = self.offline;
+        downloader
+    }
+
+    /// Images a Dockerfile builds on, skipping earlier stages and `scratch`.
+    fn dockerfile_base_images(dockerfile: &Path) -> Result<Vec<String>> {
+        let mut stages: Vec<String> = vec![];
+        let mut images: Vec<String> = vec![];
+
+        for line in fs::read_to_string(dockerfile)?.lines() {
+            let mut words = line.split_whitespace();
+            if !words
+                .next()
+                .is_some_and(|word| word.eq_ignore_ascii_case("FROM"))
+            {
+                continue;
+            }
+
+            let mut words = words.filter(|word| !word.starts_with("--"));
+            let Some(image) = words.next() else {
+                continue;
+            };
+
+            if image != "scratch" && !stages.iter().any(|stage| stage == image) {
+                if image.contains('$') {
+                    warn!(
+                        "Cannot check whether `{}` is available offline, it depends on a build argument",
+                        image
+                    );
+                } else {
+                    images.push(image.to_string());
+                }
+            }
+
+            if let (Some(keyword), Some(stage)) = (words.next(), words.next()) {
+                if keyword.eq_ignore_ascii_case("AS") {
+                    stages.push(stage.to_string());
+                }
             }
-            CustomBackend::None => {}
         }
 
-        container_engine::run_image(
-            &self.container_engine,
-            image_tag.into(),
-            vec![
-                (
-                    self.plugin_root
-                        .join("backend")
-                        .canonicalize()?
-                        .to_str()
-                        .unwrap()
-                        .into(),
-                    "/backend".into(),
-                ),
-                (
-                    self.tmp_build_root.join("bin").to_str().unwrap().into(),
-                    "/backend/out".into(),
-                ),
-                (
-                    self.plugin_root.canonicalize()?.to_str().unwrap().into(),
-                    "/plugin".into(),
-                ),
-            ],
-            self.build_as_root.clone(),
-            self.build_with_dev.clone(),
-        )
-        .await
+        Ok(images)
     }
 
-    pub async fn copy_remote_binaries(&self) -> Result<()> {
-        let package_json_file = std::fs::read_to_string(self.plugin_root.join("package.json"))
-            .expect("Failed to read package.json");
-        let json: Value =
-            serde_json::from_str(&package_json_file).expect("Failed to parse package.json");
-        let bin_dir = self.tmp_build_root.join("bin");
+    /// Makes sure everything the build needs is already on this machine, so an
+    /// offline build fails before doing any work.
+    pub async fn check_offline_artifacts(&self) -> Result<()> {
+        let mut images = vec![];
+        // The native engine never runs containers, so it needs no images at all
+        if self.container_engine.uses_containers() {
+            images.push(self.docker_image.clone());
+
+            if let CustomBackend::Dockerfile = self.plugin.custom_backend {
+                images.extend(Builder::dockerfile_base_images(
+                    &self.plugin_root.join("backend").join("Dockerfile"),
+                )?);
+            }
+        }
+
+        let mut missing: Vec<String> = vec![];
 
-        let mut any_binaries: bool = false;
-        let mut remote_binary_bundling: bool = false;
-        if json["remote_binary_bundling"].as_bool().is_some() {
-            if json["remote_binary_bundling"].as_bool().unwrap() == true {
-                remote_binary_bundling = true
-            }
-        }
-        if let Some(remote_binary) = json["remote_binary"].as_array() {
-            if !remote_binary.is_empty() {
-                any_binaries = true;
-                if remote_binary_bundling {
-                    for binary in remote_binary {
-                        let url = binary["url"]
-                            .as_str()
-                            .expect("Failed to get URL from remote_binary config");
-                        let expected_checksum = binary["sha256hash"]
-                            .as_str()
-                            .expect("Failed to get sha256hash from remote_binary config");
-                        let dest_filename = binary["name"]
-                            .as_str()
-                            .expect("Failed to get name from remote_binary config");
-
-                        let response = reqwest::get(url)
-                            .await
-                            .expect("Failed to download remote_binary");
-                        let buffer = response
-                            .bytes()
-                            .await
-                            .expect("Failed to read remote_binary GET response");
-
-                        let mut hasher = Sha256::new();
-                        hasher.update(&buffer);
-                        let result = hasher.finalize();
-                        let checksum = format!("{:x}", result);
-
-                        if checksum == expected_checksum {
-                            info!("Checksums match for file at URL: {}", url);
-
-                            std::fs::create_dir_all(&bin_dir).expect("Failed to create directory");
-                            let filepath = bin_dir.join(dest_filename);
-                            std::fs::write(&filepath, &buffer).expect("Failed to write file");
-                            info!("File saved to: {:?}", filepath);
-                        } else {
-                            error!("Checksums do not match for file at URL: {}", url);
-                            panic!("Bad checksum for file defined in remote_binary")
+        for image in images.into_iter().unique() {
+            if !self.engine.image_exists(&image).await? {
+                missing.push(format!(
+                    "container image `{}` (run `{} pull {}` while online)",
+                    image,
+                    self.container_engine.bin_name(),
+                    image
+                ));
+            }
+        }
+
+        let binaries = RemoteBinaries::from_package_json(&self.plugin_root.join("package.json"))?;
+        if binaries.bundling {
+            let downloader = self.downloader();
+            for binary in &binaries.entries {
+                if downloader.locate(binary)?.is_none() {
+                    missing.push(format!(
+                        "remote_binary `{}` with sha256 {} (not in the download cache{})",
+                        binary.name,
+                        binary.checksum(),
+                        match &self.binaries_dir {
+                            Some(dir) => format!(" or {}", dir.display()),
+                            None => "".to_string(),
                         }
-                    }
+                    ));
                 }
             }
         }
 
-        if !remote_binary_bundling {
-            info!("Plugin does not want to bundle binaries during build");
+        match missing.is_empty() {
+            true => Ok(()),
+            false => Err(anyhow!(
+                "Cannot build offline, {} artifact(s) are missing:\n  - {}",
+                missing.len(),
+                missing.join("\n  - ")
+            )),
         }
+    }
+
+    /// Downloads remote binaries to a staging directory, see `install_remote_binaries`.
+    pub async fn fetch_remote_binaries(&self) -> Result<()> {
+        let binaries = RemoteBinaries::from_package_json(&self.plugin_root.join("package.json"))?;
+        let staging_dir = self.tmp_build_root.join(REMOTE_BINARIES_DIR);
 
-        if !any_binaries {
+        if binaries.entries.is_empty() {
             info!("Plugin does not require any remote binaries");
+            return Ok(());
+        }
+
+        if !binaries.bundling {
+            info!("Plugin does not want to bundle binaries during build");
+            return Ok(());
+        }
+
+        let downloader = self.downloader();
+        for (index, binary) in binaries.entries.iter().enumerate() {
+            downloader
+                .fetch(binary, &staging_dir.join(&binary.name))
+                .await
+                .with_context(|| format!("Failed to fetch remote_binary[{}]", index))?;
         }
 
         Ok(())
     }
 
+    /// Moves the fetched remote binaries into `bin/`, once the backend is built.
+    pub fn install_remote_binaries(&self) -> Result<()> {
+        let staging_dir = self.tmp_build_root.join(REMOTE_BINARIES_DIR);
+        if !staging_dir.exists() {
+            return Ok(());
+        }
+
+        let bin_dir = self.tmp_build_root.join("bin");
+        fs::create_dir_all(&bin_dir)?;
+        for entry in fs::read_dir(&staging_dir)? {
+            let entry = entry?;
+            fs::rename(entry.path(), bin_dir.join(entry.file_name()))?;
+        }
+
+        fs::remove_dir(&staging_dir)?;
+        Ok(())
+    }
+
     pub async fn build_py_modules(&self) -> Result<()> {
         let source_py_modules_dir = self.plugin_root.join("py_modules");
         let tmp_py_modules_dir = self.tmp_build_root.join("py_modules");
@@ -189,12 +525,13 @@ impl Builder {
 
         info!("Building py_modules");
 
-        self.copy_py_modules(source_py_modules_dir, tmp_py_modules_dir)?;
+        self.copy_dir(source_py_modules_dir, tmp_py_modules_dir)?;
 
         Ok(())
     }
 
-    fn copy_py_modules(&self, src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<()> {
+    /// Recursively copies `src` to `dst`, leaving out `__pycache__` directories.
+    fn copy_dir(&self, src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<()> {
         fs::create_dir_all(&dst)?;
 
         let src = src.as_ref();
@@ -216,7 +553,7 @@ impl Builder {
                     continue;
                 }
 
-                self.copy_py_modules(entry.path(), to)?;
+                self.copy_dir(entry.path(), to)?;
             } else if file_type.is_file() {
                 fs::copy(entry.path(), to)?;
             }
@@ -228,22 +565,44 @@ impl Builder {
     fn zip_path(
         &self,
         filename: &str,
+        root: &Path,
         path: PathBuf,
-        zip: &mut ZipWriter<File>,
-        opts: FileOptions,
+        zip: &mut PluginZip,
+        mode: Option<u32>,
     ) -> Result<()> {
         let name = path
-            .strip_prefix(&self.tmp_build_root)
+            .strip_prefix(root)
             .map(|name| name.to_path_buf())
             .and_then(|name| {
                 name.strip_prefix("defaults")
                     .map(|path| path.to_path_buf())
                     .or(Ok(name))
-            })
-            .map(|name| Path::new(filename).join(name))?;
+            })?;
+
+        if self.zip_rules.is_excluded(&name) {
+            info!("Excluding {:?}", name);
+            return Ok(());
+        }
+
+        if !zip.names.insert(name.clone()) {
+            return Ok(());
+        }
+
+        let mode = self.zip_rules.mode(&name).or(mode);
+        let relative_name = name;
+        let name = Path::new(filename).join(&relative_name);
 
         info!("Zipping {:?}", name);
 
+        let mut opts = FileOptions::default().unix_permissions(match path.is_file() {
+            true => mode.unwrap_or(0o644),
+            false => mode.unwrap_or(0o755),
+        });
+
+        if self.reproducible {
+            opts = opts.last_modified_time(self.zip_timestamp());
+        }
+
         if path.is_file() {
             let bytes = std::fs::read(&path).unwrap();
 
@@ -257,23 +616,24 @@ impl Builder {
             if method == CompressionMethod::Deflated {
                 opts = match self.compression_level {
                     Some(level) => opts.compression_level(Some(level)),
-                    None => opts.compression_level(Some(9))
+                    None => opts.compression_level(Some(9)),
                 }
             }
 
-            zip.start_file(name.to_str().unwrap(), opts)?;
+            zip.writer.start_file(name.to_str().unwrap(), opts)?;
+            zip.sizes.push((relative_name, bytes.len() as u64));
 
-            zip.write_all(&bytes)?;
+            zip.writer.write_all(&bytes)?;
         } else if !name.as_os_str().is_empty() {
-            zip.add_directory(name.to_str().unwrap(), opts)?;
+            zip.writer.add_directory(name.to_str().unwrap(), opts)?;
         }
 
         Ok(())
     }
 
-    pub fn zip_plugin(&self) -> Result<()> {
-        info!("Zipping plugin");
-        let filename: String = match &self.output_filename_source {
+    /// Name of the plugin's directory inside the zip
+    pub fn output_name(&self) -> String {
+        match &self.output_filename_source {
             FilenameSource::PluginName => self.plugin.meta.name.clone(),
             FilenameSource::Directory => self
                 .plugin_root
@@ -281,59 +641,97 @@ impl Builder {
                 .unwrap()
                 .to_string_lossy()
                 .to_string(),
-        };
-        let zip_filename = format!(
-            "{}{}.zip",
-            &filename,
+        }
+    }
+
+    /// Name shared by the zip and the files written next to it
+    pub fn zip_stem(&self) -> String {
+        format!(
+            "{}{}",
+            self.output_name(),
             if self.build_with_dev {
                 "-dev".to_string()
             } else {
                 "".to_string()
             }
-        );
-        let file = std::fs::File::create(&self.output_root.join(zip_filename))
-            .expect("Could not create zip file");
-        let mut zip = zip::ZipWriter::new(file);
+        )
+    }
+
+    pub fn zip_filename(&self) -> String {
+        format!("{}.zip", self.zip_stem())
+    }
+
+    pub fn manifest_filename(&self) -> String {
+        format!("{}.manifest.json", self.zip_stem())
+    }
+
+    /// Parses `SOURCE_DATE_EPOCH`, which only reproducible builds use.
+    fn source_date_epoch(epoch: Option<String>, reproducible: bool) -> Result<Option<i64>> {
+        match epoch {
+            Some(epoch) if reproducible => epoch
+                .trim()
+                .parse()
+                .map(Some)
+                .with_context(|| format!("Invalid SOURCE_DATE_EPOCH `{}`", epoch)),
+            Some(_) => {
+                warn!("Ignoring SOURCE_DATE_EPOCH, the build is not reproducible");
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Timestamp given to every entry of a reproducible zip
+    fn zip_timestamp(&self) -> DateTime {
+        self.source_date_epoch
+            .and_then(|epoch| OffsetDateTime::from_unix_timestamp(epoch).ok())
+            .and_then(|time| DateTime::try_from(time).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn zip_plugin(&self) -> Result<ZipReport> {
+        info!("Zipping plugin");
+        let filename = self.output_name();
+        let zip_path = self.output_root.join(self.zip_filename());
+        let file = std::fs::File::create(&zip_path).expect("Could not create zip file");
+        let mut zip = PluginZip {
+            writer: zip::ZipWriter::new(file),
+            names: HashSet::new(),
+            sizes: vec![],
+        };
 
         /// Directory that needs to be zipped
         struct DirDirective<'a> {
             path: &'a str,
             mandatory: bool,
-            permissions: FileOptions,
+            /// Unix permissions for everything inside, instead of the defaults
+            mode: Option<u32>,
         }
 
         let directories = vec![
             DirDirective {
                 path: "dist",
                 mandatory: true,
-                permissions: FileOptions::default(),
+                mode: None,
             },
             DirDirective {
                 path: "bin",
                 mandatory: false,
-                permissions: FileOptions::default().unix_permissions(0o755),
+                mode: Some(0o755),
             },
             DirDirective {
                 path: "defaults",
                 mandatory: false,
-                permissions: FileOptions::default(),
+                mode: None,
             },
             DirDirective {
                 path: "py_modules",
                 mandatory: false,
-                permissions: FileOptions::default().unix_permissions(0o755),
+                mode: Some(0o755),
             },
         ];
 
-        let expected_files = vec![
-            "LICENSE",
-            "main.py",
-            "package.json",
-            "plugin.json",
-            "README.md",
-        ]
-        .into_iter()
-        .map(|f| f.to_string());
+        let expected_files = PLUGIN_FILES.into_iter().map(|f| f.to_string());
 
         let python_files = glob(&format!("{}/*.py", self.tmp_build_root.to_string_lossy()))
             .unwrap()
@@ -343,20 +741,19 @@ impl Builder {
                     .unwrap()
                     .to_string_lossy()
                     .to_string()
-            })
-            .into_iter();
+            });
 
         let files = expected_files.chain(python_files).unique();
 
         for file in files {
             let full_path = self.tmp_build_root.join(&file);
-            self.zip_path(&filename, full_path, &mut zip, Default::default())?;
+            self.zip_path(&filename, &self.tmp_build_root, full_path, &mut zip, None)?;
         }
 
         for directory in directories {
-            let full_path = self.tmp_build_root.join(&directory.path);
+            let full_path = self.tmp_build_root.join(directory.path);
 
-            if directory.mandatory == false && !full_path.exists() {
+            if !directory.mandatory && !full_path.exists() {
                 info!(
                     "Optional directory {} not found. Continuing",
                     &directory.path
@@ -364,21 +761,62 @@ impl Builder {
                 continue;
             }
 
-            let dir_entries = WalkDir::new(full_path).follow_links(self.follow_symlinks);
+            let mut dir_entries = WalkDir::new(full_path).follow_links(self.follow_symlinks);
+            if self.reproducible {
+                dir_entries = dir_entries.sort_by_file_name();
+            }
+
             for entry in dir_entries {
                 let file = entry?;
                 self.zip_path(
                     &filename,
+                    &self.tmp_build_root,
                     file.path().to_path_buf(),
                     &mut zip,
-                    directory.permissions,
+                    directory.mode,
                 )?;
             }
         }
 
-        zip.finish()?;
+        for path in self.zip_rules.included(&self.plugin_root)? {
+            let mut entries = WalkDir::new(path).follow_links(self.follow_symlinks);
+            if self.reproducible {
+                entries = entries.sort_by_file_name();
+            }
 
-        Ok(())
+            for entry in entries {
+                self.zip_path(
+                    &filename,
+                    &self.plugin_root,
+                    entry?.path().to_path_buf(),
+                    &mut zip,
+                    None,
+                )?;
+            }
+        }
+
+        zip.writer.finish()?;
+
+        Ok(ZipReport {
+            files: zip.sizes,
+            zip_size: fs::metadata(&zip_path)?.len(),
+        })
+    }
+
+    fn check_flags(plugin: &Plugin, build_as_root: bool, build_with_dev: bool) {
+        if build_as_root && !plugin.meta.requires_root() {
+            warn!(
+                "Building as root, but plugin.json does not declare the `{}` flag. The plugin will not run as root on the Deck, and files built as root may not be usable by it.",
+                Flag::Root
+            );
+        }
+
+        if !build_with_dev && plugin.meta.flags.contains(&Flag::Debug) {
+            warn!(
+                "plugin.json declares the `{}` flag, which is usually not wanted in a production build.",
+                Flag::Debug
+            );
+        }
     }
 
     fn validate_tmp_build_root(tmp_build_root: &PathBuf) -> Result<&PathBuf> {
@@ -390,31 +828,454 @@ impl Builder {
         })
     }
 
+    /// Makes sure the builder image is available and records exactly which
+    /// image the build is going to use.
+    pub async fn resolve_builder_image(&mut self, log: &StageLog) -> Result<()> {
+        if !self.container_engine.uses_containers() {
+            return Ok(());
+        }
+
+        if !self.offline && !self.engine.image_exists(&self.docker_image).await? {
+            info!("Pulling {}", self.docker_image);
+            self.engine.pull_image(&self.docker_image, log).await?;
+        }
+
+        let digest = self.engine.image_digest(&self.docker_image).await?;
+        info!("Using builder image {} ({})", self.docker_image, digest);
+        self.builder_image_digest = Some(digest);
+
+        Ok(())
+    }
+
+    /// Builds the image the custom backend is built in and records exactly
+    /// which image that is, so a changed image rebuilds the backend.
+    pub async fn resolve_backend_image(&mut self, log: &StageLog) -> Result<()> {
+        if !self.container_engine.uses_containers() || !self.plugin_root.join("backend").exists() {
+            return Ok(());
+        }
+
+        let image = match self.plugin.custom_backend {
+            CustomBackend::Dockerfile => {
+                self.engine
+                    .build_image(
+                        &self.plugin_root.join("backend").join("Dockerfile"),
+                        &self.plugin.meta.name.to_ascii_lowercase().replace(" ", "-"),
+                        log,
+                    )
+                    .await?
+            }
+            CustomBackend::None => self.docker_image.clone(),
+        };
+
+        let digest = self.engine.image_digest(&image).await?;
+        info!("Using backend image {} ({})", image, digest);
+        self.backend_image = Some(image);
+        self.backend_image_digest = Some(digest);
+
+        Ok(())
+    }
+
+    /// Lists the files in the zip with their digests.
+    fn zipped_files(zip: &Path) -> Result<Vec<ManifestFile>> {
+        let mut archive = zip::ZipArchive::new(File::open(zip)?)?;
+        let mut files = vec![];
+
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            if entry.is_dir() {
+                continue;
+            }
+
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut entry, &mut hasher)?;
+            files.push(ManifestFile {
+                path: entry.name().to_string(),
+                size: entry.size(),
+                sha256: format!("{:x}", hasher.finalize()),
+            });
+        }
+
+        Ok(files)
+    }
+
+    /// Removes the zip and what [`Builder::write_manifest`] wrote next to it,
+    /// so a failed build leaves nothing behind that looks like a release.
+    fn remove_artifacts(&self) -> Result<()> {
+        let zip_filename = self.zip_filename();
+
+        for filename in [
+            format!("{}.sha256", zip_filename),
+            self.manifest_filename(),
+            zip_filename,
+        ] {
+            let location = self.output_root.join(filename);
+            match fs::remove_file(&location) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(err)
+                        .with_context(|| format!("Could not remove {}", location.display()))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes `<zip>.sha256` and `<name>.manifest.json` next to the zip.
+    pub fn write_manifest(&self) -> Result<()> {
+        let zip_filename = self.zip_filename();
+        let zip = self.output_root.join(&zip_filename);
+        let zip_sha256 = BlobCache::hash_file(&zip)?;
+
+        let checksum_location = self.output_root.join(format!("{}.sha256", zip_filename));
+        std::fs::write(
+            &checksum_location,
+            format!("{}  {}\n", zip_sha256, zip_filename),
+        )
+        .with_context(|| format!("Could not write {}", checksum_location.display()))?;
+
+        let package: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(
+            self.plugin_root.join("package.json"),
+        )?)?;
+        let uses_containers = self.container_engine.uses_containers();
+
+        let manifest = Manifest {
+            name: self.plugin.meta.name.clone(),
+            version: package["version"].as_str().map(String::from),
+            mode: match self.build_with_dev {
+                true => "development".into(),
+                false => "production".into(),
+            },
+            cli_version: env!("CARGO_PKG_VERSION").into(),
+            builder_image: uses_containers.then(|| self.docker_image.clone()),
+            builder_image_digest: self.builder_image_digest.clone(),
+            zip: ManifestFile {
+                path: zip_filename,
+                size: zip.metadata()?.len(),
+                sha256: zip_sha256,
+            },
+            files: Builder::zipped_files(&zip)?,
+        };
+
+        let location = self.output_root.join(self.manifest_filename());
+        std::fs::write(&location, serde_json::to_string_pretty(&manifest)? + "\n")
+            .with_context(|| format!("Could not write {}", location.display()))
+    }
+
+    /// Hashes everything `stage` depends on.
+    fn fingerprint(&self, stage: Stage) -> Result<Fingerprint> {
+        let mut fingerprint = Fingerprint::new(stage.name());
+        fingerprint
+            .value("cli", env!("CARGO_PKG_VERSION"))
+            .value("engine", self.container_engine.bin_name())
+            .value("root", self.build_as_root)
+            .value("dev", self.build_with_dev);
+
+        // Build output and deploy settings that live in the plugin root
+        let generated = [
+            self.plugin_root.join("dist"),
+            self.plugin_root.join("backend").join("out"),
+            self.plugin_root.join("deck.json"),
+            self.output_root.clone(),
+            self.tmp_build_root.clone(),
+        ];
+
+        match stage {
+            // The backend container sees the whole plugin root at /plugin
+            Stage::Backend => {
+                fingerprint
+                    .value(
+                        "backend_image",
+                        self.backend_image_digest.as_deref().unwrap_or("none"),
+                    )
+                    .tree(&self.plugin_root, &generated)?;
+            }
+            Stage::Frontend => {
+                let mut exclude = vec![
+                    self.plugin_root.join("backend"),
+                    self.plugin_root.join("py_modules"),
+                ];
+                exclude.extend(generated);
+
+                fingerprint
+                    .value(
+                        "builder_image",
+                        self.builder_image_digest.as_deref().unwrap_or("none"),
+                    )
+                    .tree(&self.plugin_root, &exclude)?;
+            }
+            Stage::RemoteBinaries => {
+                let binaries =
+                    RemoteBinaries::from_package_json(&self.plugin_root.join("package.json"))?;
+                fingerprint.value("bundling", binaries.bundling);
+                for binary in binaries.entries {
+                    fingerprint.value(
+                        &binary.name,
+                        format!("{} {}", binary.url, binary.checksum()),
+                    );
+                }
+            }
+            Stage::PyModules => {
+                fingerprint
+                    .value("follow_symlinks", self.follow_symlinks)
+                    .tree(&self.plugin_root.join("py_modules"), &[])?;
+            }
+        }
+
+        Ok(fingerprint)
+    }
+
+    /// Removes `path` from the build directory if it exists.
+    fn remove_build_path(path: &Path) -> Result<()> {
+        let removed = match path.is_dir() {
+            true => fs::remove_dir_all(path),
+            false => fs::remove_file(path),
+        };
+
+        match removed {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(Builder::removal_error(path, err))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Explains a failure to remove `path`, which is usually a file written by a
+    /// container that ran as root.
+    fn removal_error(path: &Path, err: std::io::Error) -> anyhow::Error {
+        let hint = match err.kind() {
+            std::io::ErrorKind::PermissionDenied => format!(
+                " Files written by containers running as root (--build-as-root on a rootful engine) can only be removed as root, e.g. with `sudo rm -rf {}`.",
+                path.display()
+            ),
+            _ => "".to_string(),
+        };
+
+        anyhow::Error::new(err).context(format!("Could not remove {}.{}", path.display(), hint))
+    }
+
+    /// Removes what `stage` left in the build directory last time.
+    fn clean_stage(&self, stage: Stage) -> Result<()> {
+        let paths = match stage {
+            Stage::Backend => vec![self.tmp_build_root.join("bin")],
+            Stage::RemoteBinaries => vec![
+                self.tmp_build_root.join("bin"),
+                self.tmp_build_root.join(REMOTE_BINARIES_DIR),
+            ],
+            Stage::PyModules => vec![self.tmp_build_root.join("py_modules")],
+            Stage::Frontend => fs::read_dir(&self.tmp_build_root)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .filter_ok(|path| {
+                    !path.file_name().is_some_and(|name| {
+                        ["bin", "py_modules", FINGERPRINTS_DIR]
+                            .contains(&name.to_string_lossy().as_ref())
+                    })
+                })
+                .collect::<std::io::Result<_>>()?,
+        };
+
+        paths
+            .iter()
+            .try_for_each(|path| Builder::remove_build_path(path))
+            .with_context(|| format!("Could not clean up the previous {} build", stage.name()))
+    }
+
+    /// Stages whose inputs changed since they last completed, along with their
+    /// new fingerprints. Their previous output is removed.
+    fn stale_stages(&self, fingerprints: &Fingerprints) -> Result<Vec<(Stage, Fingerprint)>> {
+        let mut current = vec![];
+        for stage in Stage::ALL {
+            current.push((stage, self.fingerprint(stage)?));
+        }
+
+        let changed: Vec<Stage> = current
+            .iter()
+            .filter(|(stage, fingerprint)| !fingerprints.matches(stage.name(), fingerprint))
+            .map(|(stage, _)| *stage)
+            .collect();
+
+        let mut stale = vec![];
+        for (stage, fingerprint) in current {
+            if changed.iter().any(|other| stage.shares_output(other)) {
+                fingerprints.invalidate(stage.name())?;
+                self.clean_stage(stage)?;
+                stale.push((stage, fingerprint));
+            } else {
+                info!("{} is up to date, skipping", stage.name());
+            }
+        }
+
+        Ok(stale)
+    }
+
+    async fn build_stage(&self, stage: Stage) -> Result<()> {
+        let log = self.output.stage(stage.name())?;
+
+        match stage {
+            Stage::Backend => self.build_backend(&log).await.inspect_err(|_| log.replay()).context(
+                "Failed to build backend. There might be more information in the output above.",
+            ),
+            Stage::Frontend => self.build_frontend(&log).await.inspect_err(|_| log.replay()).context(
+                "Failed to build frontend. There might be more information in the output above.",
+            ),
+            Stage::RemoteBinaries => self.fetch_remote_binaries().await.context(
+                "Failed to copy remote binaries. There might be more information in the output above.",
+            ),
+            Stage::PyModules => self.build_py_modules().await.context(
+                "Failed to build py_modules. There might be more information in the output above.",
+            ),
+        }
+    }
+
+    /// Runs `stages` concurrently, at most `jobs` at a time. Every stage is
+    /// allowed to finish, so no container is left behind when one fails.
+    async fn build_stages(
+        &self,
+        stages: &[(Stage, Fingerprint)],
+        fingerprints: &Fingerprints,
+    ) -> Result<()> {
+        let jobs = Semaphore::new(self.jobs.max(1));
+
+        let results = join_all(stages.iter().map(|(stage, fingerprint)| async {
+            let _permit = jobs.acquire().await?;
+            self.build_stage(*stage).await?;
+
+            // Only complete once installed into bin/
+            if *stage != Stage::RemoteBinaries {
+                fingerprints.record(stage.name(), fingerprint)?;
+            }
+
+            Ok::<_, anyhow::Error>(())
+        }))
+        .await;
+
+        let mut errors = results.into_iter().filter_map(Result::err);
+        match errors.next() {
+            Some(first) => {
+                for other in errors {
+                    error!("{:#}", other);
+                }
+                Err(first)
+            }
+            None => Ok(()),
+        }
+    }
+
     pub async fn run(&mut self) -> Result<()> {
-        info!("Creating temporary build directory");
-        std::fs::remove_dir_all(&self.tmp_build_root).ok();
-        std::fs::create_dir_all(&self.tmp_build_root)
-            .context("Temporary build directory already exists")?;
+        if self.force {
+            info!("Removing previous build output");
+            Builder::remove_build_path(&self.tmp_build_root)?;
+        }
+        std::fs::create_dir_all(&self.tmp_build_root).with_context(|| {
+            format!(
+                "Could not create build directory {}",
+                self.tmp_build_root.display()
+            )
+        })?;
+
+        if self.offline {
+            info!("Checking for offline artifacts");
+            self.check_offline_artifacts().await?;
+        }
+
+        let log = self.output.stage("pull")?;
+        self.resolve_builder_image(&log)
+            .await
+            .inspect_err(|_| log.replay())
+            .context("Failed to resolve the builder image.")?;
+
+        let log = self.output.stage("backend-image")?;
+        self.resolve_backend_image(&log)
+            .await
+            .inspect_err(|_| log.replay())
+            .context(
+                "Failed to build the backend image. There might be more information in the output above.",
+            )?;
 
         info!("Building plugin");
-        self.build_backend().await.context(
-            "Failed to build backend. There might be more information in the output above.",
-        )?;
-        self.build_frontend().await.context(
-            "Failed to build frontend. There might be more information in the output above.",
-        )?;
-        self.copy_remote_binaries().await.context(
-            "Failed to copy remote binaries. There might be more information in the output above.",
-        )?;
-        self.build_py_modules().await.context(
-            "Failed to build py_modules. There might be more information in the output above.",
-        )?;
-        self.zip_plugin().context("Failed to zip plugin.")?;
+        let fingerprints = Fingerprints::new(self.tmp_build_root.join(FINGERPRINTS_DIR));
+        let stale = self.stale_stages(&fingerprints)?;
+        self.build_stages(&stale, &fingerprints).await?;
+
+        if let Some((_, fingerprint)) = stale
+            .iter()
+            .find(|(stage, _)| *stage == Stage::RemoteBinaries)
+        {
+            self.install_remote_binaries()
+                .context("Failed to install remote binaries.")?;
+            fingerprints.record(Stage::RemoteBinaries.name(), fingerprint)?;
+        }
+
+        let report = self.zip_plugin().context("Failed to zip plugin.")?;
+        report.log();
+
+        if let Some(max_size) = self.max_size.filter(|max| report.zip_size > *max) {
+            self.remove_artifacts()?;
+            return Err(anyhow!(
+                "{} is {}, over the maximum size of {}. Exclude what it doesn't need, starting with the largest directories listed above.",
+                self.zip_filename(),
+                format_size(report.zip_size),
+                format_size(max_size)
+            ));
+        }
+        self.write_manifest()
+            .context("Failed to write the build manifest.")?;
 
         Ok(())
     }
 
-    pub fn new(
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        plugin_root: PathBuf,
+        output_root: PathBuf,
+        tmp_build_root: PathBuf,
+        build_as_root: bool,
+        build_with_dev: bool,
+        follow_symlinks: bool,
+        output_filename_source: FilenameSource,
+        container_engine: ContainerEngine,
+        engine_transport: EngineTransport,
+        compression_method: CompressMethod,
+        compression_level: Option<i32>,
+        offline: bool,
+        binaries_dir: Option<PathBuf>,
+        builder_image: Option<String>,
+        force: bool,
+        jobs: usize,
+        reproducible: bool,
+        max_size: Option<u64>,
+        output: BuildOutput,
+    ) -> Result<Self> {
+        let (container_engine, engine) =
+            container_engine::connect(&container_engine, &engine_transport).await?;
+
+        Builder::with_engine(
+            engine,
+            plugin_root,
+            output_root,
+            tmp_build_root,
+            build_as_root,
+            build_with_dev,
+            follow_symlinks,
+            output_filename_source,
+            container_engine,
+            compression_method,
+            compression_level,
+            offline,
+            binaries_dir,
+            builder_image,
+            force,
+            jobs,
+            reproducible,
+            max_size,
+            output,
+        )
+    }
+
+    /// Creates a builder that uses an already connected `engine`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_engine(
+        engine: Arc<dyn Engine>,
         plugin_root: PathBuf,
         output_root: PathBuf,
         tmp_build_root: PathBuf,
@@ -425,27 +1286,80 @@ impl Builder {
         container_engine: ContainerEngine,
         compression_method: CompressMethod,
         compression_level: Option<i32>,
+        offline: bool,
+        binaries_dir: Option<PathBuf>,
+        builder_image: Option<String>,
+        force: bool,
+        jobs: usize,
+        reproducible: bool,
+        max_size: Option<u64>,
+        output: BuildOutput,
     ) -> Result<Self> {
         if !output_root.exists() {
             std::fs::create_dir(&output_root)?;
         }
 
-        container_engine::ensure_availability(&container_engine)?;
-
         Builder::validate_tmp_build_root(&tmp_build_root).unwrap();
 
-        let output_random_padding: String = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let plugin = Plugin::new(plugin_root.clone()).context("Could not create plugin")?;
+        Builder::check_flags(&plugin, build_as_root, build_with_dev);
+
+        let docker_image = builder_image
+            .or(plugin.config.build.builder_image.clone())
+            .unwrap_or(DEFAULT_BUILDER_IMAGE.to_owned());
+
+        let plugin_root = plugin_root
+            .canonicalize()
+            .expect("Could not find plugin root");
+
+        let zip_rules = plugin
+            .config
+            .zip
+            .rules(&plugin_root)
+            .context("Invalid zip settings")?;
+
+        let max_size = match max_size {
+            Some(max_size) => Some(max_size),
+            None => plugin
+                .config
+                .zip
+                .max_size
+                .as_deref()
+                .map(parse_size)
+                .transpose()
+                .context("Invalid max_size")?,
+        };
+
+        let source_date_epoch =
+            Builder::source_date_epoch(std::env::var("SOURCE_DATE_EPOCH").ok(), reproducible)?;
+
+        // Kept between builds so unchanged stages can be skipped, one per plugin
+        let build_dir = format!(
+            "{}-{}",
+            plugin_root
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy(),
+            &format!(
+                "{:x}",
+                Sha256::digest(plugin_root.to_string_lossy().as_bytes())
+            )[..12]
+        );
 
         Ok(Self {
-            plugin: Plugin::new(plugin_root.clone()).expect("Could not create plugin"),
-            plugin_root: plugin_root
-                .canonicalize()
-                .expect("Could not find plugin root"),
+            plugin,
+            plugin_root,
             output_root: output_root
                 .canonicalize()
                 .expect("Could not find output root"),
-            tmp_build_root: tmp_build_root.join(output_random_padding),
-            docker_image: "ghcr.io/steamdeckhomebrew/builder:latest".to_owned(),
+            tmp_build_root: tmp_build_root.join(build_dir),
+            docker_image,
+            builder_image_digest: None,
+            backend_image: None,
+            backend_image_digest: None,
+            engine,
+            output,
+            zip_rules,
             build_as_root,
             build_with_dev,
             follow_symlinks,
@@ -453,6 +1367,740 @@ impl Builder {
             container_engine,
             compression_method,
             compression_level,
+            offline,
+            binaries_dir,
+            force,
+            jobs,
+            reproducible,
+            max_size,
+            source_date_epoch,
+            search_path: None,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container_engine::{Call, MockEngine, UserMapping};
+    use serde_json::json;
+    use tempfile::TempDir;
+    use uzers::{get_effective_gid, get_effective_uid};
+
+    /// A minimal plugin, optionally with a custom backend
+    fn plugin(with_backend: bool) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+
+        let plugin_json = json!({ "name": "Test Plugin", "author": "Tester", "flags": [] });
+        fs::write(root.join("plugin.json"), plugin_json.to_string()).unwrap();
+        fs::write(
+            root.join("package.json"),
+            json!({ "name": "test-plugin", "version": "1.0.0" }).to_string(),
+        )
+        .unwrap();
+        fs::write(root.join("main.py"), "class Plugin: pass\n").unwrap();
+        fs::write(root.join("LICENSE"), "BSD-3-Clause\n").unwrap();
+
+        if with_backend {
+            fs::create_dir(root.join("backend")).unwrap();
+            fs::write(root.join("backend/Dockerfile"), "FROM alpine:3\n").unwrap();
+        }
+
+        dir
+    }
+
+    /// Pretends to be the builder image, copying the plugin files to `/out`.
+    fn frontend_engine() -> MockEngine {
+        MockEngine::new()
+            .with_output("/out", "dist/index.js", b"export default {};\n")
+            .with_output("/out", "plugin.json", br#"{"name":"Test Plugin"}"#)
+            .with_output("/out", "main.py", b"class Plugin: pass\n")
+    }
+
+    fn builder(
+        engine: Arc<MockEngine>,
+        plugin: &TempDir,
+        build: &TempDir,
+        build_as_root: bool,
+        build_with_dev: bool,
+        offline: bool,
+    ) -> Builder {
+        builder_with_output(
+            engine,
+            plugin,
+            build,
+            build_as_root,
+            build_with_dev,
+            offline,
+            BuildOutput::default(),
+        )
+    }
+
+    fn builder_with_output(
+        engine: Arc<MockEngine>,
+        plugin: &TempDir,
+        build: &TempDir,
+        build_as_root: bool,
+        build_with_dev: bool,
+        offline: bool,
+        output: BuildOutput,
+    ) -> Builder {
+        Builder::with_engine(
+            engine,
+            plugin.path().to_path_buf(),
+            build.path().join("out"),
+            build.path().join("tmp"),
+            build_as_root,
+            build_with_dev,
+            true,
+            FilenameSource::PluginName,
+            ContainerEngine::Docker,
+            CompressMethod::Deflate,
+            None,
+            offline,
+            None,
+            None,
+            false,
+            4,
+            true,
+            None,
+            output,
+        )
+        .unwrap()
+    }
+
+    fn zip_entries(path: &Path) -> Vec<(String, Option<u32>)> {
+        let mut archive = zip::ZipArchive::new(File::open(path).unwrap()).unwrap();
+        (0..archive.len())
+            .map(|index| {
+                let entry = archive.by_index(index).unwrap();
+                (entry.name().to_string(), entry.unix_mode())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn builds_frontend_in_builder_image() {
+        let plugin = plugin(false);
+        let build = tempfile::tempdir().unwrap();
+        let engine = Arc::new(frontend_engine());
+        let mut builder = builder(engine.clone(), &plugin, &build, false, false, false);
+
+        builder.run().await.unwrap();
+
+        let plugin_root = plugin.path().canonicalize().unwrap();
+        assert_eq!(
+            engine.calls(),
+            vec![
+                Call::ImageExists(DEFAULT_BUILDER_IMAGE.into()),
+                Call::PullImage(DEFAULT_BUILDER_IMAGE.into()),
+                Call::ImageDigest(DEFAULT_BUILDER_IMAGE.into()),
+                Call::RunImage(RunOptions {
+                    image: DEFAULT_BUILDER_IMAGE.into(),
+                    binds: vec![
+                        (plugin_root.to_str().unwrap().into(), "/plugin".into()),
+                        (
+                            builder.tmp_build_root.to_str().unwrap().into(),
+                            "/out".into()
+                        ),
+                    ],
+                    run_as_root: false,
+                    run_with_dev: false,
+                    offline: false,
+                }),
+            ]
+        );
+
+        let run = &engine.runs()[0];
+        assert_eq!(
+            run.user(UserMapping::Rootful),
+            Some(format!("{}:{}", get_effective_uid(), get_effective_gid()))
+        );
+        assert_eq!(run.env(), vec!["RELEASE_TYPE=production".to_string()]);
+
+        let names: Vec<String> = zip_entries(&build.path().join("out/Test Plugin.zip"))
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        for expected in [
+            "Test Plugin/main.py",
+            "Test Plugin/plugin.json",
+            "Test Plugin/dist/index.js",
+        ] {
+            assert!(names.iter().any(|name| name == expected), "{names:?}");
+        }
+        assert!(build.path().join("out/Test Plugin.manifest.json").exists());
+    }
+
+    #[tokio::test]
+    async fn builds_custom_backend_as_root_for_development() {
+        let plugin = plugin(true);
+        let build = tempfile::tempdir().unwrap();
+        let engine = Arc::new(
+            frontend_engine()
+                .with_image(DEFAULT_BUILDER_IMAGE)
+                .with_output("/backend/out", "backend", b"#!/bin/sh\n"),
+        );
+        let mut builder = builder(engine.clone(), &plugin, &build, true, true, false);
+
+        builder.run().await.unwrap();
+
+        let plugin_root = plugin.path().canonicalize().unwrap();
+        assert!(engine.calls().contains(&Call::BuildImage(
+            plugin_root.join("backend/Dockerfile"),
+            "test-plugin".into()
+        )));
+        assert!(!engine
+            .calls()
+            .contains(&Call::PullImage(DEFAULT_BUILDER_IMAGE.into())));
+
+        let runs = engine.runs();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].image, "test-plugin");
+        assert_eq!(
+            runs[0].binds,
+            vec![
+                (
+                    plugin_root.join("backend").to_str().unwrap().into(),
+                    "/backend".into()
+                ),
+                (
+                    builder.tmp_build_root.join("bin").to_str().unwrap().into(),
+                    "/backend/out".into()
+                ),
+                (plugin_root.to_str().unwrap().into(), "/plugin".into()),
+            ]
+        );
+        assert_eq!(runs[1].image, DEFAULT_BUILDER_IMAGE);
+        for run in &runs {
+            assert_eq!(run.user(UserMapping::Rootful), None);
+            assert_eq!(run.env(), vec!["RELEASE_TYPE=development".to_string()]);
+            assert_eq!(
+                run.bind_specs(true)
+                    .iter()
+                    .filter(|spec| spec.ends_with(":z"))
+                    .count(),
+                run.binds.len()
+            );
+        }
+
+        let entries = zip_entries(&build.path().join("out/Test Plugin-dev.zip"));
+        let (_, mode) = entries
+            .iter()
+            .find(|(name, _)| name == "Test Plugin/bin/backend")
+            .expect("backend binary is zipped");
+        assert_eq!(mode.unwrap() & 0o777, 0o755);
+    }
+
+    #[tokio::test]
+    async fn offline_build_fails_before_running_containers() {
+        let plugin = plugin(true);
+        let build = tempfile::tempdir().unwrap();
+        let engine = Arc::new(frontend_engine().with_image("alpine:3"));
+        let mut builder = builder(engine.clone(), &plugin, &build, false, false, true);
+
+        let error = format!("{:#}", builder.run().await.unwrap_err());
+
+        assert!(error.contains(DEFAULT_BUILDER_IMAGE), "{error}");
+        assert!(!error.contains("alpine:3"), "{error}");
+        assert!(engine.runs().is_empty());
+        assert!(!build.path().join("out/Test Plugin.zip").exists());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    async fn builds_frontend_natively() {
+        let plugin = plugin(false);
+        let build = tempfile::tempdir().unwrap();
+        let bin = tempfile::tempdir().unwrap();
+        let npm = bin.path().join("npm");
+        fs::write(
+            &npm,
+            "#!/bin/sh\n\
+             echo \"$RELEASE_TYPE $*\" >> \"$0.log\"\n\
+             if [ \"$1\" = run ]; then mkdir -p dist && echo 'export default {};' > dist/index.js; fi\n",
+        )
+        .unwrap();
+        fs::set_permissions(&npm, os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+
+        let engine = Arc::new(MockEngine::new());
+        let mut builder = builder(engine.clone(), &plugin, &build, false, true, true);
+        builder.container_engine = ContainerEngine::Native;
+        builder.search_path = Some(bin.path().as_os_str().to_owned());
+
+        builder.run().await.unwrap();
+
+        assert_eq!(
+            fs::read_to_string(bin.path().join("npm.log")).unwrap(),
+            "development install --offline\ndevelopment run build\n"
+        );
+        assert!(engine.calls().is_empty());
+
+        let names: Vec<String> = zip_entries(&build.path().join("out/Test Plugin-dev.zip"))
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        for expected in [
+            "Test Plugin/main.py",
+            "Test Plugin/plugin.json",
+            "Test Plugin/package.json",
+            "Test Plugin/dist/index.js",
+        ] {
+            assert!(names.iter().any(|name| name == expected), "{names:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn native_offline_build_needs_no_images() {
+        let plugin = plugin(true);
+        let build = tempfile::tempdir().unwrap();
+        let engine = Arc::new(MockEngine::new());
+        let mut builder = builder(engine.clone(), &plugin, &build, false, false, true);
+        builder.container_engine = ContainerEngine::Native;
+
+        builder.check_offline_artifacts().await.unwrap();
+
+        assert!(engine.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reports_failing_frontend_build() {
+        let plugin = plugin(false);
+        let build = tempfile::tempdir().unwrap();
+        let engine = Arc::new(
+            frontend_engine()
+                .with_image(DEFAULT_BUILDER_IMAGE)
+                .failing(DEFAULT_BUILDER_IMAGE),
+        );
+        let mut builder = builder(engine.clone(), &plugin, &build, false, false, false);
+
+        let error = format!("{:#}", builder.run().await.unwrap_err());
+
+        assert!(error.starts_with("Failed to build frontend"), "{error}");
+        assert!(!build.path().join("out/Test Plugin.zip").exists());
+    }
+
+    #[tokio::test]
+    async fn saves_stage_logs_to_log_file() {
+        let plugin = plugin(true);
+        let build = tempfile::tempdir().unwrap();
+        let log_file = build.path().join("build.log");
+        let engine = Arc::new(
+            frontend_engine()
+                .with_image(DEFAULT_BUILDER_IMAGE)
+                .failing(DEFAULT_BUILDER_IMAGE),
+        );
+        let output = BuildOutput::new(true, Some(&log_file)).unwrap();
+        let mut builder = builder_with_output(engine, &plugin, &build, false, false, false, output);
+
+        builder.run().await.unwrap_err();
+
+        let log = fs::read_to_string(&log_file).unwrap();
+        assert_eq!(
+            log.lines().collect::<Vec<_>>(),
+            vec![
+                "[backend] running test-plugin",
+                &format!("[frontend] running {}", DEFAULT_BUILDER_IMAGE),
+                "[frontend] error: build failed",
+            ]
+        );
+
+        let stage_log = |stage| fs::read_to_string(build.path().join(stage)).unwrap();
+        assert_eq!(stage_log("build.backend.log"), "running test-plugin\n");
+        assert_eq!(
+            stage_log("build.frontend.log"),
+            format!("running {}\nerror: build failed\n", DEFAULT_BUILDER_IMAGE)
+        );
+    }
+
+    #[test]
+    fn explains_build_files_that_cannot_be_removed() {
+        let build = tempfile::tempdir().unwrap();
+        let bin = build.path().join("bin");
+
+        Builder::remove_build_path(&bin).unwrap();
+        fs::create_dir_all(bin.join("backend")).unwrap();
+        Builder::remove_build_path(&bin).unwrap();
+        assert!(!bin.exists());
+
+        let error = format!(
+            "{:#}",
+            Builder::removal_error(&bin, std::io::ErrorKind::PermissionDenied.into())
+        );
+        assert!(
+            error.starts_with(&format!("Could not remove {}.", bin.display())),
+            "{error}"
+        );
+        assert!(
+            error.contains(&format!("`sudo rm -rf {}`", bin.display())),
+            "{error}"
+        );
+
+        let error = format!(
+            "{:#}",
+            Builder::removal_error(&bin, std::io::ErrorKind::Other.into())
+        );
+        assert!(!error.contains("sudo"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn skips_unchanged_stages() {
+        let plugin = plugin(true);
+        let build = tempfile::tempdir().unwrap();
+        let images = |engine: &MockEngine| -> Vec<String> {
+            engine.runs().into_iter().map(|run| run.image).collect()
+        };
+        let new_engine = || {
+            Arc::new(
+                frontend_engine()
+                    .with_image(DEFAULT_BUILDER_IMAGE)
+                    .with_output("/backend/out", "backend", b"#!/bin/sh\n"),
+            )
+        };
+
+        let engine = new_engine();
+        builder(engine.clone(), &plugin, &build, false, false, false)
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(images(&engine), vec!["test-plugin", DEFAULT_BUILDER_IMAGE]);
+
+        let engine = new_engine();
+        builder(engine.clone(), &plugin, &build, false, false, false)
+            .run()
+            .await
+            .unwrap();
+        assert!(images(&engine).is_empty());
+        let names: Vec<String> = zip_entries(&build.path().join("out/Test Plugin.zip"))
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert!(names.contains(&"Test Plugin/bin/backend".to_string()));
+        assert!(names.contains(&"Test Plugin/dist/index.js".to_string()));
+
+        fs::write(plugin.path().join("backend/main.c"), "int main;\n").unwrap();
+        let engine = new_engine();
+        builder(engine.clone(), &plugin, &build, false, false, false)
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(images(&engine), vec!["test-plugin"]);
+
+        let engine = new_engine();
+        let mut forced = builder(engine.clone(), &plugin, &build, false, false, false);
+        forced.force = true;
+        forced.run().await.unwrap();
+        assert_eq!(images(&engine), vec!["test-plugin", DEFAULT_BUILDER_IMAGE]);
+    }
+
+    #[tokio::test]
+    async fn rebuilds_backend_when_plugin_root_or_image_changes() {
+        let plugin = plugin(true);
+        let build = tempfile::tempdir().unwrap();
+        let backend_runs = |engine: &MockEngine| {
+            engine
+                .runs()
+                .into_iter()
+                .filter(|run| run.image == "test-plugin")
+                .count()
+        };
+        let new_engine = |digest: &str| {
+            Arc::new(
+                frontend_engine()
+                    .with_image(DEFAULT_BUILDER_IMAGE)
+                    .with_digest("test-plugin", digest)
+                    .with_output("/backend/out", "backend", b"#!/bin/sh\n"),
+            )
+        };
+        let run = |engine: Arc<MockEngine>| {
+            let mut builder = builder(engine, &plugin, &build, false, false, false);
+            async move { builder.run().await.unwrap() }
+        };
+
+        let engine = new_engine("sha256:1");
+        run(engine.clone()).await;
+        assert_eq!(backend_runs(&engine), 1);
+
+        let engine = new_engine("sha256:1");
+        run(engine.clone()).await;
+        assert_eq!(backend_runs(&engine), 0);
+
+        fs::write(plugin.path().join("shared.py"), "VERSION = 2\n").unwrap();
+        let engine = new_engine("sha256:1");
+        run(engine.clone()).await;
+        assert_eq!(backend_runs(&engine), 1);
+
+        let engine = new_engine("sha256:2");
+        run(engine.clone()).await;
+        assert_eq!(backend_runs(&engine), 1);
+        assert!(engine
+            .calls()
+            .contains(&Call::ImageDigest("test-plugin".into())));
+    }
+
+    #[tokio::test]
+    async fn runs_stages_concurrently_up_to_jobs() {
+        for (jobs, expected) in [(4, 2), (1, 1)] {
+            let plugin = plugin(true);
+            let build = tempfile::tempdir().unwrap();
+            let engine = Arc::new(
+                frontend_engine()
+                    .with_image(DEFAULT_BUILDER_IMAGE)
+                    .with_delay(std::time::Duration::from_millis(100)),
+            );
+            let mut builder = builder(engine.clone(), &plugin, &build, false, false, false);
+            builder.jobs = jobs;
+
+            builder.run().await.unwrap();
+
+            assert_eq!(engine.runs().len(), 2);
+            assert_eq!(engine.max_running(), expected, "with {jobs} jobs");
+        }
+    }
+
+    #[tokio::test]
+    async fn builds_byte_identical_zips() {
+        let plugin = plugin(false);
+        let files = [
+            ("dist/index.js", "export default {};\n"),
+            ("dist/chunks/b.js", "b\n"),
+            ("dist/chunks/a.js", "a\n"),
+            ("dist/zz.css", "\n"),
+            ("plugin.json", r#"{"name":"Test Plugin"}"#),
+            ("main.py", "class Plugin: pass\n"),
+        ];
+
+        let mut hashes = vec![];
+        for reversed in [false, true] {
+            let build = tempfile::tempdir().unwrap();
+            let mut engine = MockEngine::new().with_image(DEFAULT_BUILDER_IMAGE);
+            let ordered: Vec<_> = match reversed {
+                false => files.iter().collect(),
+                true => files.iter().rev().collect(),
+            };
+            for (file, contents) in ordered {
+                engine = engine.with_output("/out", file, contents.as_bytes());
+            }
+
+            builder(Arc::new(engine), &plugin, &build, false, false, false)
+                .run()
+                .await
+                .unwrap();
+
+            let zip = build.path().join("out/Test Plugin.zip");
+            hashes.push(BlobCache::hash_file(&zip).unwrap());
+
+            let mut archive = zip::ZipArchive::new(File::open(&zip).unwrap()).unwrap();
+            for index in 0..archive.len() {
+                let entry = archive.by_index(index).unwrap();
+                let modified = entry.last_modified();
+                assert_eq!(
+                    (modified.datepart(), modified.timepart()),
+                    (
+                        DateTime::default().datepart(),
+                        DateTime::default().timepart()
+                    ),
+                    "{}",
+                    entry.name()
+                );
+            }
+        }
+
+        assert_eq!(hashes[0], hashes[1]);
+    }
+
+    #[tokio::test]
+    async fn honors_source_date_epoch() {
+        let plugin = plugin(false);
+        let build = tempfile::tempdir().unwrap();
+        let engine = Arc::new(frontend_engine().with_image(DEFAULT_BUILDER_IMAGE));
+        let mut builder = builder(engine, &plugin, &build, false, false, false);
+        // 2023-11-14 22:13:20 UTC
+        builder.source_date_epoch = Some(1_700_000_000);
+
+        builder.run().await.unwrap();
+
+        let zip = build.path().join("out/Test Plugin.zip");
+        let mut archive = zip::ZipArchive::new(File::open(zip).unwrap()).unwrap();
+        let modified = archive.by_index(0).unwrap().last_modified();
+        assert_eq!(
+            (modified.year(), modified.month(), modified.day()),
+            (2023, 11, 14)
+        );
+        assert_eq!(
+            (modified.hour(), modified.minute(), modified.second()),
+            (22, 13, 20)
+        );
+
+        let epoch = |value: &str, reproducible| {
+            Builder::source_date_epoch(Some(value.to_string()), reproducible)
+        };
+        assert_eq!(epoch(" 1700000000\n", true).unwrap(), Some(1_700_000_000));
+        assert!(epoch("yesterday", true).is_err());
+        assert_eq!(epoch("yesterday", false).unwrap(), None);
+        assert_eq!(Builder::source_date_epoch(None, true).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn writes_checksum_and_manifest() {
+        let plugin = plugin(false);
+        let build = tempfile::tempdir().unwrap();
+        let engine = Arc::new(frontend_engine().with_image(DEFAULT_BUILDER_IMAGE));
+
+        builder(engine, &plugin, &build, false, true, false)
+            .run()
+            .await
+            .unwrap();
+
+        let out = build.path().join("out");
+        let zip_sha256 = BlobCache::hash_file(&out.join("Test Plugin-dev.zip")).unwrap();
+        assert_eq!(
+            fs::read_to_string(out.join("Test Plugin-dev.zip.sha256")).unwrap(),
+            format!("{}  Test Plugin-dev.zip\n", zip_sha256)
+        );
+
+        let manifest: Manifest = serde_json::from_str(
+            &fs::read_to_string(out.join("Test Plugin-dev.manifest.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(manifest.name, "Test Plugin");
+        assert_eq!(manifest.version.as_deref(), Some("1.0.0"));
+        assert_eq!(manifest.mode, "development");
+        assert_eq!(manifest.cli_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            manifest.builder_image.as_deref(),
+            Some(DEFAULT_BUILDER_IMAGE)
+        );
+        assert_eq!(
+            manifest.builder_image_digest,
+            Some(format!(
+                "{}@sha256:{}",
+                DEFAULT_BUILDER_IMAGE,
+                "0".repeat(64)
+            ))
+        );
+        assert_eq!(manifest.zip.path, "Test Plugin-dev.zip");
+        assert_eq!(manifest.zip.sha256, zip_sha256);
+
+        let main_py = manifest
+            .files
+            .iter()
+            .find(|file| file.path == "Test Plugin/main.py")
+            .expect("main.py is listed");
+        assert_eq!(main_py.size, 19);
+        assert_eq!(
+            main_py.sha256,
+            format!("{:x}", Sha256::digest(b"class Plugin: pass\n"))
+        );
+        assert!(manifest
+            .files
+            .iter()
+            .any(|file| file.path == "Test Plugin/dist/index.js"));
+    }
+
+    #[tokio::test]
+    async fn applies_zip_rules() {
+        let plugin = plugin(false);
+        let root = plugin.path();
+        fs::write(
+            root.join("decky.toml"),
+            r#"
+[zip]
+include = ["assets", "CHANGELOG.md"]
+exclude = ["dist/*.map"]
+
+[zip.permissions]
+"assets/tools" = 0o755
+"#,
+        )
+        .unwrap();
+        fs::write(
+            root.join(".deckyignore"),
+            "# not shipped\nLICENSE\nassets/raw/\n",
+        )
+        .unwrap();
+        fs::write(root.join("CHANGELOG.md"), "# 1.0.0\n").unwrap();
+        for file in [
+            "assets/logo.png",
+            "assets/tools/convert",
+            "assets/raw/logo.xcf",
+        ] {
+            fs::create_dir_all(root.join(file).parent().unwrap()).unwrap();
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        let build = tempfile::tempdir().unwrap();
+        let engine = frontend_engine().with_output("/out", "dist/index.js.map", b"{}");
+        builder(Arc::new(engine), &plugin, &build, false, false, false)
+            .run()
+            .await
+            .unwrap();
+
+        let entries = zip_entries(&build.path().join("out/Test Plugin.zip"));
+        let mode = |name: &str| {
+            entries
+                .iter()
+                .find(|(entry, _)| entry == &format!("Test Plugin/{}", name))
+                .map(|(_, mode)| mode.unwrap() & 0o777)
+        };
+
+        assert_eq!(mode("CHANGELOG.md"), Some(0o644));
+        assert_eq!(mode("assets/logo.png"), Some(0o644));
+        assert_eq!(mode("assets/tools/convert"), Some(0o755));
+        assert_eq!(mode("dist/index.js"), Some(0o644));
+        assert_eq!(mode("dist/index.js.map"), None);
+        assert_eq!(mode("LICENSE"), None);
+        assert_eq!(mode("assets/raw/"), None);
+        assert_eq!(mode("assets/raw/logo.xcf"), None);
+    }
+
+    #[tokio::test]
+    async fn enforces_max_size() {
+        let plugin = plugin(false);
+        fs::write(
+            plugin.path().join("decky.toml"),
+            "[zip]\nmax_size = \"8 KiB\"\n",
+        )
+        .unwrap();
+
+        // Pseudo-random, so it doesn't compress below the budget
+        let mut state = 1u32;
+        let vendor: Vec<u8> = (0..32 * 1024)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+
+        let build = tempfile::tempdir().unwrap();
+        let engine = frontend_engine().with_output("/out", "dist/vendor/big.js", &vendor);
+        let mut builder = builder(Arc::new(engine), &plugin, &build, false, false, false);
+
+        // Left over from an earlier build that fit
+        let out = build.path().join("out");
+        for leftover in ["Test Plugin.zip.sha256", "Test Plugin.manifest.json"] {
+            fs::write(out.join(leftover), "").unwrap();
+        }
+
+        assert_eq!(builder.max_size, Some(8 * 1024));
+        let err = builder.run().await.unwrap_err();
+        assert!(err.to_string().contains("over the maximum size of 8.0 KiB"));
+        assert_eq!(fs::read_dir(&out).unwrap().count(), 0);
+
+        let report = builder.zip_plugin().unwrap();
+        assert!(report.zip_size > 32 * 1024);
+        assert!(report.uncompressed_size() > 32 * 1024);
+        assert_eq!(
+            report.largest_files()[0],
+            ("dist/vendor/big.js".into(), 32 * 1024)
+        );
+        assert_eq!(
+            report.largest_dirs()[..2],
+            [
+                ("dist".into(), 32 * 1024 + 19),
+                ("dist/vendor".into(), 32 * 1024)
+            ]
+        );
+    }
+}
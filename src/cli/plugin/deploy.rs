@@ -1,32 +1,247 @@
-use dirs::home_dir;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
-use anyhow::{anyhow, Result};
-use boolinator::Boolinator;
+use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 
-use log::info;
+use log::{info, warn};
 use rand::distributions::{Alphanumeric, DistString};
 
 use crate::cli::plugin::build::Builder;
-use crate::cli::CompressMethod;
-use crate::{cli::FilenameSource, cli::ContainerEngine, plugin::Plugin};
+use crate::cli::prompt;
+use crate::cli::{CompressMethod, DeployTransport};
+use crate::config::{DeckProfile, DeckProfiles, DeckSecrets};
+use crate::deck_transport::{self, parse_port, shell_quote, DeckConnection, Transport, DECK_USER};
+use crate::{cli::ContainerEngine, cli::EngineTransport, cli::FilenameSource, output::BuildOutput};
 
-#[derive(Serialize, Deserialize, Clone)]
+/// Per-plugin settings for the Deck to deploy to
+const DECKFILE: &str = "deck.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct DeckFile {
     pub deckip: String,
     pub deckport: String,
     pub deckpass: String,
     pub deckkey: String,
     pub deckdir: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deckuser: Option<String>,
+}
+
+impl Default for DeckFile {
+    fn default() -> Self {
+        Self {
+            deckip: "".to_string(),
+            deckport: "22".to_string(),
+            deckpass: "".to_string(),
+            deckkey: "".to_string(),
+            deckdir: "/home/deck".to_string(),
+            deckuser: None,
+        }
+    }
+}
+
+impl DeckFile {
+    /// Reads deck.json from `plugin_root`, if there is one.
+    pub fn read(plugin_root: &Path) -> Result<Option<Self>> {
+        let location = plugin_root.join(DECKFILE);
+        if !location.exists() {
+            return Ok(None);
+        }
+
+        info!("Loading {}...", DECKFILE);
+        if Self::is_git_ignored(plugin_root, DECKFILE) == Some(false) {
+            warn!(
+                "{} is not ignored by git. It holds details about your Deck and maybe its password, so add it to .gitignore.",
+                DECKFILE
+            );
+        }
+
+        std::fs::read_to_string(&location)
+            .map_err(anyhow::Error::from)
+            .and_then(|str| serde_json::from_str(&str).map_err(Into::into))
+            .map(Some)
+            .with_context(|| format!("Could not parse {}", location.display()))
+    }
+
+    /// Whether git ignores `file`, or None outside of a git repository
+    fn is_git_ignored(root: &Path, file: &str) -> Option<bool> {
+        let status = Command::new("git")
+            .args(["check-ignore", "-q", file])
+            .current_dir(root)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .ok()?;
+
+        match status.code() {
+            Some(0) => Some(true),
+            Some(1) => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Asks the user how to reach their Deck and writes the answers to deck.json.
+    /// The password is never written there, but can be kept in the secrets file.
+    fn setup(plugin_root: &Path, secrets_location: &Path) -> Result<Self> {
+        let location = plugin_root.join(DECKFILE);
+
+        if !prompt::is_interactive() {
+            return Err(anyhow!(
+                "{} does not exist. Create it, pass --deck-ip or --deck, or run this command in a terminal to set it up.",
+                location.display()
+            ));
+        }
+
+        println!("{} does not exist, let's set it up.", location.display());
+
+        let defaults = DeckFile::default();
+        let deckip = prompt::text("IP address or hostname of the Deck", None)?;
+        // Checked now, a broken deck.json would fail every later deploy
+        let deckport = loop {
+            match parse_port(&prompt::text("SSH port", Some(&defaults.deckport))?) {
+                Ok(port) => break port.to_string(),
+                Err(err) => println!("{}", err),
+            }
+        };
+        let deckuser = prompt::text("User", Some(DECK_USER))?;
+
+        let default_key = ["id_ed25519", "id_rsa"]
+            .into_iter()
+            .filter_map(|name| dirs::home_dir().map(|home| home.join(".ssh").join(name)))
+            .find(|path| path.exists());
+        let deckkey = match prompt::confirm("Log in with an SSH key?", default_key.is_some())? {
+            true => prompt::text(
+                "Path to the private key",
+                default_key
+                    .map(|key| key.to_string_lossy().to_string())
+                    .as_deref(),
+            )?,
+            false => "".to_string(),
+        };
+
+        let mut deck = DeckFile {
+            deckip,
+            deckport,
+            deckkey,
+            deckuser: Some(deckuser),
+            ..defaults
+        };
+
+        if prompt::confirm(
+            &format!(
+                "Save the Deck's password for sudo in {}?",
+                secrets_location.display()
+            ),
+            true,
+        )? {
+            let password = prompt::password("Password")?;
+            let destination = DeckConnection::from_deckfile(&deck)?.destination();
+
+            let mut secrets = DeckSecrets::load(secrets_location)?;
+            secrets.passwords.insert(destination, password.clone());
+            secrets.save(secrets_location)?;
+            info!("Saved the password in {}", secrets_location.display());
+
+            std::fs::write(&location, serde_json::to_string_pretty(&deck)?)?;
+            deck.deckpass = password;
+        } else {
+            std::fs::write(&location, serde_json::to_string_pretty(&deck)?)?;
+        }
+        info!("Wrote {}", location.display());
+
+        if Self::is_git_ignored(plugin_root, DECKFILE) == Some(false) {
+            warn!(
+                "{} is not ignored by git. Add it to .gitignore so your Deck's details aren't committed.",
+                DECKFILE
+            );
+        }
+
+        Ok(deck)
+    }
+}
+
+/// The Deck to deploy to, combined from everywhere its settings can come from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Deck {
+    pub connection: DeckConnection,
+    /// Home directory that holds `homebrew/`
+    pub dir: String,
+}
+
+impl Deck {
+    pub fn from_deckfile(deck: &DeckFile) -> Result<Self> {
+        Ok(Self {
+            connection: DeckConnection::from_deckfile(deck)?,
+            dir: deck.deckdir.clone(),
+        })
+    }
+
+    /// Replaces the settings that `profile` has.
+    pub fn apply_profile(&mut self, profile: &DeckProfile) {
+        if let Some(ip) = &profile.ip {
+            self.connection.host = ip.clone();
+        }
+        if let Some(port) = profile.port {
+            self.connection.port = port;
+        }
+        if let Some(user) = &profile.user {
+            self.connection.user = user.clone();
+        }
+        if let Some(key) = &profile.key {
+            self.connection.key = Some(key.clone());
+        }
+        if let Some(dir) = &profile.dir {
+            self.dir = dir.clone();
+        }
+    }
+
+    /// Settings for the Deck to deploy to. Later sources override earlier ones:
+    /// defaults, deck.json in `plugin_root`, `profile`, then `overrides` and
+    /// `password` from the command line. Passwords not given anywhere else come
+    /// from the secrets file at `secrets_location`.
+    pub fn resolve(
+        plugin_root: &Path,
+        profile: Option<&DeckProfile>,
+        overrides: &DeckProfile,
+        password: Option<&str>,
+        secrets_location: &Path,
+    ) -> Result<Self> {
+        let deckfile = match DeckFile::read(plugin_root)? {
+            Some(deckfile) => deckfile,
+            None if profile.is_some() || overrides.ip.is_some() => DeckFile::default(),
+            None => DeckFile::setup(plugin_root, secrets_location)?,
+        };
+
+        let mut deck = Deck::from_deckfile(&deckfile)?;
+        for profile in profile.into_iter().chain([overrides]) {
+            deck.apply_profile(profile);
+        }
+        if let Some(password) = password {
+            deck.connection.password = password.to_string();
+        }
+
+        if deck.connection.host.is_empty() {
+            return Err(anyhow!(
+                "No Deck to deploy to. Pass --deck-ip or --deck, or set deckip in deck.json."
+            ));
+        }
+
+        if deck.connection.password.is_empty() {
+            let secrets = DeckSecrets::load(secrets_location)?;
+            if let Some(password) = secrets.passwords.get(&deck.connection.destination()) {
+                deck.connection.password = password.clone();
+            }
+        }
+
+        Ok(deck)
+    }
 }
 
 #[derive(Clone)]
 pub struct Deployer {
     builder: Builder,
 
-    pub plugin: Plugin,
     pub plugin_root: PathBuf,
     pub tmp_build_root: PathBuf,
     pub deck_ip: Option<String>,
@@ -34,237 +249,135 @@ pub struct Deployer {
     pub deck_pass: Option<String>,
     pub deck_key: Option<String>,
     pub deck_dir: Option<String>,
+    /// Name of the profile in decks.toml to deploy to
+    pub deck_profile: Option<String>,
+    pub transport: DeployTransport,
+    /// The deck user can sudo without a password
+    pub no_sudo: bool,
 }
 
 impl Deployer {
-    pub async fn create_folders(&mut self, deck: DeckFile) -> Result<()> {
+    pub fn create_folders(&self, deck: &Deck, remote: &dyn Transport) -> Result<()> {
         info!("Creating folders");
-        Command::new("ssh")
-            .args([
-                format!("deck@{}", deck.deckip),
-                "-p".to_string(),
-                format!("{}", deck.deckport),
-                format!("{}", if deck.deckkey.contains("-i ") { "-i" } else { "" }),
-                format!("{}", if deck.deckkey.contains("-i ") {
-                    deck.deckkey
-                    .replace("-i ", "")
-                    .replace("$HOME", &*home_dir().unwrap().to_string_lossy())
-                    .replace("${env:HOME}", &*home_dir().unwrap().to_string_lossy())
-                } else {"".to_string()}),
-                format!("mkdir -p {deckdir}/homebrew/pluginloader && mkdir -p {deckdir}/homebrew/plugins", deckdir = deck.deckdir),
-            ])
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .output()
-            .expect("Unable to create folders");
+        for dir in ["pluginloader", "plugins"] {
+            remote
+                .create_dir_all(&format!("{}/homebrew/{}", deck.dir, dir))
+                .context("Unable to create folders")?;
+        }
         Ok(())
     }
 
-    pub async fn chmod_folders(&mut self, deck: DeckFile) -> Result<()> {
+    pub fn chmod_folders(&self, deck: &Deck, remote: &dyn Transport) -> Result<()> {
         info!("Chmod folders");
-        Command::new("ssh")
-            .args([
-                format!("deck@{}", deck.deckip),
-                "-p".to_string(),
-                format!("{}", deck.deckport),
-                format!(
-                    "{}",
-                    if deck.deckkey.contains("-i ") {
-                        "-i"
-                    } else {
-                        ""
-                    }
-                ),
-                format!(
-                    "{}",
-                    if deck.deckkey.contains("-i ") {
-                        deck.deckkey
-                            .replace("-i ", "")
-                            .replace("$HOME", &*home_dir().unwrap().to_string_lossy())
-                            .replace("${env:HOME}", &*home_dir().unwrap().to_string_lossy())
-                    } else {
-                        "".to_string()
-                    }
+        remote
+            .run_as_root(
+                &format!(
+                    "chmod -R ug+rw {}",
+                    shell_quote(&format!("{}/homebrew/", deck.dir))
                 ),
-                format!(
-                    "echo '{}' | sudo -S chmod -R ug+rw {}/homebrew/",
-                    deck.deckpass, deck.deckdir
-                ),
-            ])
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .output()
-            .expect("Unable to chmod folders");
-        Ok(())
+                self.no_sudo,
+            )
+            .context("Unable to chmod folders")
     }
 
-    pub async fn deploy_plugin(&mut self, deck: DeckFile, filename: String) -> Result<()> {
+    pub fn deploy_plugin(
+        &self,
+        deck: &Deck,
+        remote: &dyn Transport,
+        filename: String,
+    ) -> Result<()> {
         info!("Deploying plugin");
-
-        Command::new("rsync")
-            .args([
-                "-azp".to_string(),
-                "--delete".to_string(),
-                "--chmod=D0755,F0755".to_string(),
-                // format!("--rsh='ssh -p {} {}'", deck.deckport, deck.deckkey.replace("$HOME", &*home_dir().unwrap().to_string_lossy())),
-                self.tmp_build_root
-                    .join(filename)
-                    .to_string_lossy()
-                    .to_string(),
-                format!("deck@{}:{}/homebrew/plugins", deck.deckip, deck.deckdir),
-            ])
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .output()
-            .expect("Unable to rsync");
-
-        Ok(())
+        remote
+            .sync_dir(
+                &self.tmp_build_root.join(&filename),
+                &format!("{}/homebrew/plugins/{}", deck.dir, filename),
+            )
+            .context("Unable to copy the plugin to the Deck")
     }
 
-    pub async fn restart_decky(&mut self, deck: DeckFile) -> Result<()> {
+    pub fn restart_decky(&self, remote: &dyn Transport) -> Result<()> {
         info!("Restarting decky");
-        Command::new("ssh")
-            .args([
-                format!("deck@{}", deck.deckip),
-                "-p".to_string(),
-                format!("{}", deck.deckport),
-                format!(
-                    "{}",
-                    if deck.deckkey.contains("-i ") {
-                        "-i"
-                    } else {
-                        ""
-                    }
-                ),
-                format!(
-                    "{}",
-                    if deck.deckkey.contains("-i ") {
-                        deck.deckkey
-                            .replace("-i ", "")
-                            .replace("$HOME", &*home_dir().unwrap().to_string_lossy())
-                            .replace("${env:HOME}", &*home_dir().unwrap().to_string_lossy())
-                    } else {
-                        "".to_string()
-                    }
-                ),
-                format!(
-                    "echo '{}' | sudo -S systemctl restart plugin_loader.service",
-                    deck.deckpass
-                ),
-            ])
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .output()
-            .expect("Unable to restart decky");
-        Ok(())
+        remote
+            .run_as_root("systemctl restart plugin_loader.service", self.no_sudo)
+            .context("Unable to restart decky")
     }
 
-    pub async fn run(&mut self) -> Result<()> {
-        let mut deck: DeckFile;
-        if self.deck_ip.is_some()
-            && self.deck_port.is_some()
-            && self.deck_pass.is_some()
-            && self.deck_key.is_some()
-            && self.deck_dir.is_some()
-        {
-            deck = DeckFile {
-                deckip: self.deck_ip.clone().unwrap(),
-                deckport: self.deck_port.clone().unwrap(),
-                deckpass: self.deck_pass.clone().unwrap(),
-                deckkey: self.deck_key.clone().unwrap(),
-                deckdir: self.deck_dir.clone().unwrap(),
-            };
-        } else {
-            deck = self.find_deckfile()?;
-            if self.deck_ip.is_some() {
-                deck.deckip = self.deck_ip.clone().unwrap();
-            }
-            if self.deck_port.is_some() {
-                deck.deckport = self.deck_port.clone().unwrap();
-            }
-            if self.deck_pass.is_some() {
-                deck.deckpass = self.deck_pass.clone().unwrap();
-            }
-            if self.deck_key.is_some() {
-                deck.deckkey = self.deck_key.clone().unwrap();
-            }
-            if self.deck_dir.is_some() {
-                deck.deckdir = self.deck_dir.clone().unwrap();
+    /// Settings from the command line, which override every other source
+    fn overrides(&self) -> Result<DeckProfile> {
+        Ok(DeckProfile {
+            ip: self.deck_ip.clone(),
+            port: self.deck_port.as_deref().map(parse_port).transpose()?,
+            user: None,
+            key: match &self.deck_key {
+                Some(key) => DeckConnection::key_path(key)?,
+                None => None,
+            },
+            dir: self.deck_dir.clone(),
+        })
+    }
+
+    fn resolve_deck(&self) -> Result<Deck> {
+        let profiles;
+        let profile = match &self.deck_profile {
+            Some(name) => {
+                profiles = DeckProfiles::load(&DeckProfiles::default_location()?)?;
+                Some(profiles.get(name)?)
             }
-        }
+            None => None,
+        };
 
-        self.builder.run().await.unwrap();
+        Deck::resolve(
+            &self.plugin_root,
+            profile,
+            &self.overrides()?,
+            self.deck_pass.as_deref(),
+            &DeckSecrets::default_location()?,
+        )
+    }
 
-        std::fs::remove_dir_all(&self.tmp_build_root).ok();
-        std::fs::create_dir_all(&self.tmp_build_root).ok();
+    pub async fn run(&mut self) -> Result<()> {
+        let deck = self.resolve_deck()?;
 
-        let filename: String = match &self.builder.output_filename_source {
-            FilenameSource::PluginName => self.plugin.meta.name.clone(),
-            FilenameSource::Directory => self
-                .plugin_root
-                .file_name()
-                .unwrap()
-                .to_string_lossy()
-                .to_string(),
-        };
-        let zip_filename = format!(
-            "{}{}.zip",
-            &filename,
-            if self.builder.build_with_dev {
-                "-dev".to_string()
-            } else {
-                "".to_string()
-            }
-        );
-        let file = std::fs::File::open(&self.builder.output_root.join(zip_filename))
-            .expect("Could not open zip file");
-        let mut zip = zip::ZipArchive::new(file).unwrap();
-        zip.extract(&self.tmp_build_root).unwrap();
+        self.builder.run().await?;
 
-        self.create_folders(deck.clone()).await?;
+        if self.tmp_build_root.exists() {
+            std::fs::remove_dir_all(&self.tmp_build_root)
+                .with_context(|| format!("Could not remove {}", self.tmp_build_root.display()))?;
+        }
+        std::fs::create_dir_all(&self.tmp_build_root)
+            .with_context(|| format!("Could not create {}", self.tmp_build_root.display()))?;
 
-        self.chmod_folders(deck.clone()).await?;
+        let filename = self.builder.output_name();
+        let zip_path = self.builder.output_root.join(self.builder.zip_filename());
+        let file = std::fs::File::open(&zip_path)
+            .with_context(|| format!("Could not open {}", zip_path.display()))?;
+        zip::ZipArchive::new(file)
+            .and_then(|mut zip| zip.extract(&self.tmp_build_root))
+            .with_context(|| {
+                format!(
+                    "Could not extract {} to {}",
+                    zip_path.display(),
+                    self.tmp_build_root.display()
+                )
+            })?;
 
-        self.deploy_plugin(deck.clone(), filename).await?;
+        let remote = deck_transport::connect(&self.transport, &deck.connection)?;
 
-        self.chmod_folders(deck.clone()).await?;
+        self.create_folders(&deck, remote.as_ref())?;
 
-        self.restart_decky(deck.clone()).await?;
+        self.chmod_folders(&deck, remote.as_ref())?;
 
-        Ok(())
-    }
+        self.deploy_plugin(&deck, remote.as_ref(), filename)?;
 
-    fn find_deckfile(&mut self) -> Result<DeckFile> {
-        info!("Looking for deck.json...");
-        let deckfile_location = self.plugin_root.join("deck.json");
+        self.chmod_folders(&deck, remote.as_ref())?;
 
-        self.plugin_root
-            .join("deck.json")
-            .exists()
-            .as_result(
-                deckfile_location.clone(),
-                anyhow!("Could not find deck.json"),
-            )
-            .and_then(|deckfile| std::fs::read_to_string(deckfile).map_err(Into::into))
-            .and_then(|str| serde_json::from_str::<DeckFile>(&str).map_err(Into::into))
-            .or_else(|_| {
-                let deck = DeckFile {
-                    deckip: "0.0.0.0".to_string(),
-                    deckport: "22".to_string(),
-                    deckpass: "ssap".to_string(),
-                    deckkey: "-i $HOME/.ssh/id_rsa".to_string(),
-                    deckdir: "/home/deck".to_string(),
-                };
-                std::fs::write(
-                    deckfile_location,
-                    serde_json::to_string_pretty(&deck).unwrap(),
-                )
-                .unwrap();
-                Ok(deck)
-            })
+        self.restart_decky(remote.as_ref())?;
+
+        Ok(())
     }
 
-    pub fn new(
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
         plugin_root: PathBuf,
         output_root: PathBuf,
         tmp_build_root: PathBuf,
@@ -273,13 +386,25 @@ impl Deployer {
         follow_symlinks: bool,
         output_filename_source: FilenameSource,
         container_engine: ContainerEngine,
+        engine_transport: EngineTransport,
         compression_method: CompressMethod,
         compression_level: Option<i32>,
+        offline: bool,
+        binaries_dir: Option<PathBuf>,
+        builder_image: Option<String>,
+        force: bool,
+        jobs: usize,
+        reproducible: bool,
+        max_size: Option<u64>,
+        output: BuildOutput,
         deck_ip: Option<String>,
         deck_port: Option<String>,
         deck_pass: Option<String>,
         deck_key: Option<String>,
         deck_dir: Option<String>,
+        deck_profile: Option<String>,
+        transport: DeployTransport,
+        no_sudo: bool,
     ) -> Result<Self> {
         let output_random_padding: String = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
 
@@ -292,14 +417,22 @@ impl Deployer {
             follow_symlinks,
             output_filename_source,
             container_engine,
+            engine_transport,
             compression_method,
             compression_level,
+            offline,
+            binaries_dir,
+            builder_image,
+            force,
+            jobs,
+            reproducible,
+            max_size,
+            output,
         )
-        .expect("Could not create builder");
+        .await?;
 
         Ok(Self {
             builder: builder.clone(),
-            plugin: builder.plugin.clone(),
             plugin_root,
             tmp_build_root: tmp_build_root.join(output_random_padding),
             deck_ip,
@@ -307,6 +440,135 @@ impl Deployer {
             deck_pass,
             deck_key,
             deck_dir,
+            deck_profile,
+            transport,
+            no_sudo,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_overrides_deckfile() {
+        let profiles: DeckProfiles = toml::from_str(
+            r#"
+[deck.living-room]
+ip = "192.168.1.20"
+
+[deck.dev-oled]
+ip = "10.0.0.5"
+port = 2222
+user = "dev"
+key = "/keys/my oled"
+dir = "/home/dev"
+"#,
+        )
+        .unwrap();
+
+        let deckfile = Deck::from_deckfile(&DeckFile {
+            deckip: "192.168.1.10".to_string(),
+            deckpass: "secret".to_string(),
+            deckkey: "-i /keys/default".to_string(),
+            ..Default::default()
         })
+        .unwrap();
+
+        let mut deck = deckfile.clone();
+        deck.apply_profile(profiles.get("living-room").unwrap());
+        assert_eq!(
+            deck,
+            Deck {
+                connection: DeckConnection {
+                    host: "192.168.1.20".to_string(),
+                    ..deckfile.connection.clone()
+                },
+                ..deckfile.clone()
+            }
+        );
+
+        let mut deck = deckfile.clone();
+        deck.apply_profile(profiles.get("dev-oled").unwrap());
+        assert_eq!(
+            deck,
+            Deck {
+                connection: DeckConnection {
+                    host: "10.0.0.5".to_string(),
+                    port: 2222,
+                    user: "dev".to_string(),
+                    key: Some(PathBuf::from("/keys/my oled")),
+                    password: "secret".to_string(),
+                },
+                dir: "/home/dev".to_string(),
+            }
+        );
+
+        assert!(profiles.get("office").is_err());
+    }
+
+    #[test]
+    fn keeps_secrets_private() {
+        let dir = tempfile::tempdir().unwrap();
+        let location = dir.path().join("decky").join(DeckSecrets::FILENAME);
+
+        let mut secrets = DeckSecrets::load(&location).unwrap();
+        assert!(secrets.passwords.is_empty());
+        secrets
+            .passwords
+            .insert("deck@192.168.1.20".to_string(), "secret".to_string());
+        secrets.save(&location).unwrap();
+
+        let loaded = DeckSecrets::load(&location).unwrap();
+        assert_eq!(loaded.passwords, secrets.passwords);
+
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&location).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn needs_a_terminal_to_set_up_deckfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let secrets = dir.path().join(DeckSecrets::FILENAME);
+
+        let err = Deck::resolve(dir.path(), None, &DeckProfile::default(), None, &secrets)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("deck.json does not exist"), "{}", err);
+        assert!(err.contains("run this command in a terminal"), "{}", err);
+        assert!(!dir.path().join(DECKFILE).exists());
+    }
+
+    #[test]
+    fn takes_password_from_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let location = dir.path().join(DeckSecrets::FILENAME);
+        let mut secrets = DeckSecrets::default();
+        secrets
+            .passwords
+            .insert("deck@192.168.1.20".to_string(), "secret".to_string());
+        secrets.save(&location).unwrap();
+
+        let overrides = DeckProfile {
+            ip: Some("192.168.1.20".to_string()),
+            ..Default::default()
+        };
+        let deck = Deck::resolve(dir.path(), None, &overrides, None, &location).unwrap();
+        assert_eq!(deck.connection.password, "secret");
+
+        let deck = Deck::resolve(dir.path(), None, &overrides, Some("typed"), &location).unwrap();
+        assert_eq!(deck.connection.password, "typed");
+
+        let overrides = DeckProfile {
+            ip: Some("192.168.1.21".to_string()),
+            ..Default::default()
+        };
+        let deck = Deck::resolve(dir.path(), None, &overrides, None, &location).unwrap();
+        assert_eq!(deck.connection.password, "");
     }
 }
@@ -0,0 +1,468 @@
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{Read, Seek},
+    path::PathBuf,
+};
+use zip::ZipArchive;
+
+use super::validate::Diagnostics;
+use crate::{cli::OutputFormat, util::format_size};
+
+/// Files every plugin zip has to contain, relative to the plugin's directory
+const REQUIRED_FILES: [&str; 4] = ["plugin.json", "package.json", "main.py", "dist/index.js"];
+
+/// Files the build zips even when the plugin does not have them
+const OPTIONAL_FILES: [&str; 2] = ["LICENSE", "README.md"];
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ZipEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub compressed_size: u64,
+    pub compression: String,
+    /// Unix permissions, if the zip recorded any
+    pub mode: Option<u32>,
+}
+
+/// What was found in a plugin zip
+#[derive(Serialize)]
+pub struct Inspection {
+    /// The plugin's directory inside the zip
+    pub root: Option<String>,
+    pub entries: Vec<ZipEntry>,
+    pub plugin: Option<Map<String, Value>>,
+    pub package: Option<Map<String, Value>>,
+    #[serde(skip)]
+    pub diagnostics: Diagnostics,
+}
+
+impl Inspection {
+    fn print(&self, zip_path: &str) {
+        let (size, compressed_size) = self
+            .entries
+            .iter()
+            .fold((0, 0), |(size, compressed), entry| {
+                (size + entry.size, compressed + entry.compressed_size)
+            });
+
+        println!(
+            "{}: {} entries, {} ({} compressed)",
+            zip_path,
+            self.entries.len(),
+            format_size(size),
+            format_size(compressed_size)
+        );
+        println!(
+            "{:<4}  {:<8}  {:>10}  {:>10}  name",
+            "mode", "method", "size", "compressed"
+        );
+        for entry in &self.entries {
+            println!(
+                "{:<4}  {:<8}  {:>10}  {:>10}  {}",
+                entry
+                    .mode
+                    .map(|mode| format!("{:04o}", mode))
+                    .unwrap_or_else(|| "-".to_string()),
+                entry.compression,
+                format_size(entry.size),
+                format_size(entry.compressed_size),
+                entry.name
+            );
+        }
+
+        let field = |json: &Option<Map<String, Value>>, name: &str| {
+            json.as_ref()
+                .and_then(|json| json.get(name))
+                .map(|value| match value {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                })
+                .unwrap_or_else(|| "?".to_string())
+        };
+
+        if self.plugin.is_some() {
+            println!(
+                "plugin: {} by {}, flags: {}",
+                field(&self.plugin, "name"),
+                field(&self.plugin, "author"),
+                field(&self.plugin, "flags")
+            );
+        }
+        if self.package.is_some() {
+            println!(
+                "package: {} {}",
+                field(&self.package, "name"),
+                field(&self.package, "version")
+            );
+        }
+    }
+}
+
+pub struct Inspector {
+    pub zip_path: PathBuf,
+    pub format: OutputFormat,
+}
+
+impl Inspector {
+    fn entries<R: Read + Seek>(
+        archive: &mut ZipArchive<R>,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Vec<ZipEntry>> {
+        let mut entries = vec![];
+
+        for index in 0..archive.len() {
+            let entry = archive.by_index(index)?;
+
+            if entry.enclosed_name().is_none() {
+                diagnostics.error(entry.name(), None, "points outside of the zip");
+            }
+
+            entries.push(ZipEntry {
+                name: entry.name().trim_end_matches('/').to_string(),
+                is_dir: entry.is_dir(),
+                size: entry.size(),
+                compressed_size: entry.compressed_size(),
+                compression: format!("{:?}", entry.compression()).to_lowercase(),
+                mode: entry.unix_mode().map(|mode| mode & 0o7777),
+            });
+        }
+
+        Ok(entries)
+    }
+
+    fn read_json<R: Read + Seek>(
+        archive: &mut ZipArchive<R>,
+        name: &str,
+        diagnostics: &mut Diagnostics,
+    ) -> Option<Map<String, Value>> {
+        let mut contents = String::new();
+        match archive.by_name(name) {
+            Ok(mut entry) if entry.is_file() => {
+                if let Err(err) = entry.read_to_string(&mut contents) {
+                    diagnostics.error(name, None, format!("could not be read: {}", err));
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+
+        match serde_json::from_str::<Value>(&contents) {
+            Ok(Value::Object(json)) => Some(json),
+            Ok(_) => {
+                diagnostics.error(name, None, "must be a JSON object");
+                None
+            }
+            Err(err) => {
+                diagnostics.error(name, None, format!("is not valid JSON: {}", err));
+                None
+            }
+        }
+    }
+
+    /// The single directory everything in the zip should be in
+    fn find_root(&self, entries: &[ZipEntry], diagnostics: &mut Diagnostics) -> Option<String> {
+        let zip_name = self.zip_path.to_string_lossy();
+
+        if entries.is_empty() {
+            diagnostics.error(&zip_name, None, "zip is empty");
+            return None;
+        }
+
+        let roots: BTreeSet<_> = entries
+            .iter()
+            .map(|entry| match entry.name.split_once('/') {
+                Some((root, _)) => root,
+                None if entry.is_dir => &entry.name,
+                None => "",
+            })
+            .collect();
+
+        match roots.into_iter().collect::<Vec<_>>().as_slice() {
+            [root] if !root.is_empty() => Some(root.to_string()),
+            roots => {
+                let found = roots
+                    .iter()
+                    .map(|root| match root.is_empty() {
+                        true => "files at the top level".to_string(),
+                        false => format!("`{}`", root),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                diagnostics.error(
+                    &zip_name,
+                    None,
+                    format!(
+                        "expected a single top-level plugin directory, found {}",
+                        found
+                    ),
+                );
+                None
+            }
+        }
+    }
+
+    fn check_files(entries: &[ZipEntry], root: &str, diagnostics: &mut Diagnostics) {
+        let find = |name: &str| entries.iter().find(|entry| entry.name == name);
+
+        for file in REQUIRED_FILES {
+            let name = format!("{}/{}", root, file);
+            match find(&name) {
+                None => diagnostics.error(&name, None, "file is missing"),
+                Some(entry) if entry.is_dir => {
+                    diagnostics.error(&name, None, "is a directory instead of a file")
+                }
+                Some(_) => {}
+            }
+        }
+
+        // The build adds an empty directory in place of optional files the plugin lacks
+        for file in OPTIONAL_FILES {
+            let name = format!("{}/{}", root, file);
+            if find(&name).is_some_and(|entry| entry.is_dir) {
+                diagnostics.warning(&name, None, "is a directory instead of a file");
+            }
+        }
+
+        let bin = format!("{}/bin/", root);
+        let defaults = format!("{}/defaults/", root);
+        for entry in entries {
+            if entry.name.starts_with(&defaults) {
+                diagnostics.warning(
+                    &entry.name,
+                    None,
+                    "should have been moved out of defaults/ into the plugin directory",
+                );
+            }
+
+            if entry.is_dir || !entry.name.starts_with(&bin) {
+                continue;
+            }
+
+            match entry.mode {
+                None => diagnostics.warning(
+                    &entry.name,
+                    None,
+                    "has no unix permissions and may not be executable",
+                ),
+                Some(mode) if mode & 0o111 == 0 => {
+                    diagnostics.error(&entry.name, None, "is not executable")
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    pub fn inspect(&self) -> Result<Inspection> {
+        let file = File::open(&self.zip_path)
+            .with_context(|| format!("Could not open {}", self.zip_path.display()))?;
+        let mut archive = ZipArchive::new(file)
+            .with_context(|| format!("{} is not a zip file", self.zip_path.display()))?;
+
+        let mut diagnostics = Diagnostics::default();
+        let entries = Self::entries(&mut archive, &mut diagnostics)?;
+        let root = self.find_root(&entries, &mut diagnostics);
+
+        let (plugin, package) = match &root {
+            Some(root) => (
+                Self::read_json(
+                    &mut archive,
+                    &format!("{}/plugin.json", root),
+                    &mut diagnostics,
+                ),
+                Self::read_json(
+                    &mut archive,
+                    &format!("{}/package.json", root),
+                    &mut diagnostics,
+                ),
+            ),
+            None => (None, None),
+        };
+
+        if let Some(root) = &root {
+            Self::check_files(&entries, root, &mut diagnostics);
+        }
+
+        Ok(Inspection {
+            root,
+            entries,
+            plugin,
+            package,
+            diagnostics,
+        })
+    }
+
+    pub fn run(&self) -> Result<()> {
+        info!("Inspecting {}", self.zip_path.display());
+        let inspection = self.inspect()?;
+
+        if let OutputFormat::Text = self.format {
+            inspection.print(&self.zip_path.to_string_lossy());
+        }
+
+        inspection
+            .diagnostics
+            .report_with(&self.format, &inspection)
+    }
+
+    pub fn new(zip_path: PathBuf, format: OutputFormat) -> Result<Self> {
+        if !zip_path.is_file() {
+            return Err(anyhow!("{} is not a file", zip_path.display()));
+        }
+
+        Ok(Self { zip_path, format })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::plugin::validate::Severity;
+    use serde_json::json;
+    use std::io::Write;
+    use tempfile::TempDir;
+    use zip::{write::FileOptions, ZipWriter};
+
+    /// Writes a zip with the given files and their unix permissions
+    fn zip(files: &[(&str, &str, u32)]) -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Test Plugin.zip");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+
+        for (name, contents, mode) in files {
+            let options = FileOptions::default().unix_permissions(*mode);
+            match name.strip_suffix('/') {
+                Some(name) => zip.add_directory(name, options).unwrap(),
+                None => {
+                    zip.start_file(*name, options).unwrap();
+                    zip.write_all(contents.as_bytes()).unwrap();
+                }
+            }
+        }
+
+        zip.finish().unwrap();
+        (dir, path)
+    }
+
+    fn inspect(path: PathBuf) -> Inspection {
+        Inspector::new(path, OutputFormat::Text)
+            .unwrap()
+            .inspect()
+            .unwrap()
+    }
+
+    fn messages(inspection: &Inspection) -> Vec<(Severity, String, String)> {
+        inspection
+            .diagnostics
+            .iter()
+            .map(|d| (d.severity, d.file.clone(), d.message.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn inspects_built_zip() {
+        let plugin_json = json!({ "name": "Test Plugin", "author": "Tester", "flags": [] });
+        let package_json = json!({ "name": "test-plugin", "version": "1.0.0" });
+        let (_dir, path) = zip(&[
+            ("Test Plugin/plugin.json", &plugin_json.to_string(), 0o644),
+            ("Test Plugin/package.json", &package_json.to_string(), 0o644),
+            ("Test Plugin/main.py", "class Plugin: pass\n", 0o644),
+            ("Test Plugin/dist/", "", 0o755),
+            ("Test Plugin/dist/index.js", "export default 1;\n", 0o644),
+            ("Test Plugin/bin/", "", 0o755),
+            ("Test Plugin/bin/backend", "#!/bin/sh\n", 0o755),
+        ]);
+
+        let inspection = inspect(path);
+
+        assert_eq!(messages(&inspection), vec![]);
+        assert_eq!(inspection.root.as_deref(), Some("Test Plugin"));
+        assert_eq!(inspection.entries.len(), 7);
+        let backend = inspection
+            .entries
+            .iter()
+            .find(|entry| entry.name == "Test Plugin/bin/backend")
+            .unwrap();
+        assert_eq!(backend.mode, Some(0o755));
+        assert_eq!(backend.size, 10);
+        assert_eq!(backend.compression, "deflated");
+        assert_eq!(
+            inspection.plugin.as_ref().unwrap()["author"],
+            json!("Tester")
+        );
+        assert_eq!(
+            inspection.package.as_ref().unwrap()["version"],
+            json!("1.0.0")
+        );
+    }
+
+    #[test]
+    fn reports_broken_zip() {
+        let (_dir, path) = zip(&[
+            ("Test Plugin/plugin.json", "{", 0o644),
+            ("Test Plugin/package.json", "{}", 0o644),
+            ("Test Plugin/main.py", "", 0o644),
+            ("Test Plugin/README.md/", "", 0o755),
+            ("Test Plugin/defaults/settings.json", "{}", 0o644),
+            ("Test Plugin/bin/backend", "#!/bin/sh\n", 0o644),
+        ]);
+
+        let inspection = inspect(path);
+
+        assert_eq!(
+            messages(&inspection),
+            vec![
+                (
+                    Severity::Error,
+                    "Test Plugin/plugin.json".to_string(),
+                    "is not valid JSON: EOF while parsing an object at line 1 column 1".to_string()
+                ),
+                (
+                    Severity::Error,
+                    "Test Plugin/dist/index.js".to_string(),
+                    "file is missing".to_string()
+                ),
+                (
+                    Severity::Warning,
+                    "Test Plugin/README.md".to_string(),
+                    "is a directory instead of a file".to_string()
+                ),
+                (
+                    Severity::Warning,
+                    "Test Plugin/defaults/settings.json".to_string(),
+                    "should have been moved out of defaults/ into the plugin directory".to_string()
+                ),
+                (
+                    Severity::Error,
+                    "Test Plugin/bin/backend".to_string(),
+                    "is not executable".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn requires_single_plugin_directory() {
+        let (_dir, path) = zip(&[
+            ("plugin.json", "{}", 0o644),
+            ("Test Plugin/main.py", "", 0o644),
+        ]);
+
+        let inspection = inspect(path);
+
+        assert_eq!(inspection.root, None);
+        assert_eq!(inspection.diagnostics.count(Severity::Error), 1);
+        assert!(inspection
+            .diagnostics
+            .iter()
+            .next()
+            .unwrap()
+            .message
+            .contains("files at the top level, `Test Plugin`"));
+    }
+}
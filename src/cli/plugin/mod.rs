@@ -1,8 +1,12 @@
 use super::{PluginCLI, PluginCommand};
+use crate::output::BuildOutput;
 use anyhow::Result;
 
 pub mod build;
 pub mod deploy;
+pub mod inspect;
+pub mod new;
+pub mod validate;
 
 pub async fn parse(args: &PluginCLI) -> Result<()> {
     match &args.command {
@@ -15,25 +19,60 @@ pub async fn parse(args: &PluginCLI) -> Result<()> {
             follow_symlinks,
             output_filename_source,
             container_engine,
+            engine_transport,
             compression_method,
             compression_level,
+            offline,
+            binaries_dir,
+            builder_image,
+            force,
+            jobs,
+            reproducible,
+            max_size,
+            quiet,
+            log_file,
         } => {
             build::Builder::new(
                 plugin_path.into(),
                 output_path.into(),
                 tmp_output_path.into(),
-                build_as_root.clone(),
-                build_with_dev.clone(),
-                follow_symlinks.clone(),
+                *build_as_root,
+                *build_with_dev,
+                *follow_symlinks,
                 output_filename_source.clone(),
                 container_engine.clone(),
+                engine_transport.clone(),
                 compression_method.clone(),
-                compression_level.clone(),
-            )?
+                *compression_level,
+                *offline,
+                binaries_dir.clone(),
+                builder_image.clone(),
+                *force,
+                *jobs,
+                *reproducible,
+                *max_size,
+                BuildOutput::new(*quiet, log_file.as_deref())?,
+            )
+            .await?
             .run()
             .await
         }
-        PluginCommand::New => todo!(),
+        PluginCommand::New {
+            plugin_path,
+            name,
+            author,
+            flags,
+            backend,
+            py_modules,
+        } => new::Scaffolder::new(
+            plugin_path.clone(),
+            name.clone(),
+            author.clone(),
+            flags.clone(),
+            *backend,
+            *py_modules,
+        )?
+        .run(),
         PluginCommand::Deploy {
             plugin_path,
             output_path,
@@ -43,33 +82,66 @@ pub async fn parse(args: &PluginCLI) -> Result<()> {
             follow_symlinks,
             output_filename_source,
             container_engine,
+            engine_transport,
             deck_ip,
             deck_port,
             deck_pass,
             deck_key,
             deck_dir,
+            deck_profile,
+            transport,
+            no_sudo,
             compression_method,
             compression_level,
+            offline,
+            binaries_dir,
+            builder_image,
+            force,
+            jobs,
+            reproducible,
+            max_size,
+            quiet,
+            log_file,
         } => {
             deploy::Deployer::new(
                 plugin_path.into(),
                 output_path.into(),
                 tmp_output_path.into(),
-                build_as_root.clone(),
-                build_with_dev.clone(),
-                follow_symlinks.clone(),
+                *build_as_root,
+                *build_with_dev,
+                *follow_symlinks,
                 output_filename_source.clone(),
                 container_engine.clone(),
+                engine_transport.clone(),
                 compression_method.clone(),
-                compression_level.clone(),
+                *compression_level,
+                *offline,
+                binaries_dir.clone(),
+                builder_image.clone(),
+                *force,
+                *jobs,
+                *reproducible,
+                *max_size,
+                BuildOutput::new(*quiet, log_file.as_deref())?,
                 deck_ip.clone(),
                 deck_port.clone(),
                 deck_pass.clone(),
                 deck_key.clone(),
                 deck_dir.clone(),
-            )?
+                deck_profile.clone(),
+                transport.clone(),
+                *no_sudo,
+            )
+            .await?
             .run()
             .await
         }
+        PluginCommand::Validate {
+            plugin_path,
+            format,
+        } => validate::Validator::new(plugin_path.into(), format.clone())?.run(),
+        PluginCommand::Inspect { zip_path, format } => {
+            inspect::Inspector::new(zip_path.into(), format.clone())?.run()
+        }
     }
 }
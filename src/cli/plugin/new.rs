@@ -0,0 +1,317 @@
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use serde_json::{json, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    cli::prompt,
+    plugin::{Flag, Plugin},
+};
+
+/// File that gets written into a freshly created plugin
+struct Template {
+    path: &'static str,
+    contents: &'static str,
+    executable: bool,
+}
+
+const PACKAGE_JSON: &str = include_str!("templates/package.json");
+
+const COMMON_TEMPLATES: &[Template] = &[
+    Template {
+        path: "main.py",
+        contents: include_str!("templates/main.py"),
+        executable: false,
+    },
+    Template {
+        path: "src/index.tsx",
+        contents: include_str!("templates/src/index.tsx"),
+        executable: false,
+    },
+    Template {
+        path: "rollup.config.js",
+        contents: include_str!("templates/rollup.config.js"),
+        executable: false,
+    },
+    Template {
+        path: "tsconfig.json",
+        contents: include_str!("templates/tsconfig.json"),
+        executable: false,
+    },
+    Template {
+        path: "LICENSE",
+        contents: include_str!("templates/LICENSE"),
+        executable: false,
+    },
+    Template {
+        path: "README.md",
+        contents: include_str!("templates/README.md"),
+        executable: false,
+    },
+    Template {
+        path: ".gitignore",
+        contents: include_str!("templates/gitignore"),
+        executable: false,
+    },
+];
+
+const BACKEND_TEMPLATES: &[Template] = &[
+    Template {
+        path: "backend/Dockerfile",
+        contents: include_str!("templates/backend/Dockerfile"),
+        executable: false,
+    },
+    Template {
+        path: "backend/entrypoint.sh",
+        contents: include_str!("templates/backend/entrypoint.sh"),
+        executable: true,
+    },
+];
+
+pub struct Scaffolder {
+    pub plugin_root: PathBuf,
+    pub name: String,
+    pub author: String,
+    pub flags: Vec<Flag>,
+    pub with_backend: bool,
+    pub with_py_modules: bool,
+}
+
+impl Scaffolder {
+    /// Turns a display name into something usable as a directory and npm package name.
+    fn slugify(name: &str) -> String {
+        name.to_ascii_lowercase()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+
+    fn git_user_name() -> Option<String> {
+        Command::new("git")
+            .args(["config", "user.name"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .filter(|name| !name.is_empty())
+    }
+
+    fn render(&self, contents: &str) -> Result<String> {
+        Ok(contents
+            .replace("{{name}}", &self.name)
+            .replace("{{author}}", &self.author)
+            .replace("{{name_js}}", &serde_json::to_string(&self.name)?))
+    }
+
+    fn plugin_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "author": self.author,
+            "flags": self.flags,
+            "api_version": 1,
+            "publish": {
+                "tags": [],
+                "description": "",
+                "image": ""
+            }
+        })
+    }
+
+    fn package_json(&self) -> Result<Value> {
+        let mut package: Value = serde_json::from_str(PACKAGE_JSON)?;
+        package["name"] = Value::String(Scaffolder::slugify(&self.name));
+        package["author"] = Value::String(self.author.clone());
+        Ok(package)
+    }
+
+    fn write_file(&self, path: &str, contents: &str, executable: bool) -> Result<()> {
+        let destination = self.plugin_root.join(path);
+
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        info!("Writing {}", path);
+        fs::write(&destination, contents)
+            .with_context(|| format!("Could not write {}", destination.display()))?;
+
+        #[cfg(target_family = "unix")]
+        if executable {
+            fs::set_permissions(
+                &destination,
+                std::os::unix::fs::PermissionsExt::from_mode(0o755),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn ensure_empty_root(plugin_root: &Path) -> Result<()> {
+        if plugin_root.exists() && fs::read_dir(plugin_root)?.next().is_some() {
+            return Err(anyhow!(
+                "{} already exists and is not empty",
+                plugin_root.display()
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn run(&self) -> Result<()> {
+        Scaffolder::ensure_empty_root(&self.plugin_root)?;
+        fs::create_dir_all(&self.plugin_root)?;
+
+        info!(
+            "Creating plugin {} in {}",
+            self.name,
+            self.plugin_root.display()
+        );
+
+        self.write_file(
+            "plugin.json",
+            &serde_json::to_string_pretty(&self.plugin_json())?,
+            false,
+        )?;
+        self.write_file(
+            "package.json",
+            &serde_json::to_string_pretty(&self.package_json()?)?,
+            false,
+        )?;
+
+        let backend_templates = match self.with_backend {
+            true => BACKEND_TEMPLATES,
+            false => &[],
+        };
+
+        for template in COMMON_TEMPLATES.iter().chain(backend_templates) {
+            self.write_file(
+                template.path,
+                &self.render(template.contents)?,
+                template.executable,
+            )?;
+        }
+
+        if self.with_py_modules {
+            // Left empty, everything in it is copied into the plugin zip
+            fs::create_dir(self.plugin_root.join("py_modules"))?;
+        }
+
+        Plugin::new(self.plugin_root.clone())
+            .context("The generated plugin did not pass validation. This is a bug in the CLI.")?;
+
+        info!(
+            "Created {}. Run `pnpm install` and `decky plugin build` inside it to get started.",
+            self.plugin_root.display()
+        );
+
+        Ok(())
+    }
+
+    pub fn new(
+        plugin_path: Option<PathBuf>,
+        name: Option<String>,
+        author: Option<String>,
+        mut flags: Vec<Flag>,
+        mut with_backend: bool,
+        mut with_py_modules: bool,
+    ) -> Result<Self> {
+        let interactive = name.is_none() || author.is_none();
+
+        if interactive && !prompt::is_interactive() {
+            return Err(anyhow!(
+                "Not running in a terminal. Pass --name and --author to create a plugin non-interactively."
+            ));
+        }
+
+        let name = match name {
+            Some(name) => name,
+            None => prompt::text("Plugin name", None)?,
+        };
+
+        let author = match author {
+            Some(author) => author,
+            None => prompt::text("Author", Scaffolder::git_user_name().as_deref())?,
+        };
+
+        if interactive {
+            if !flags.contains(&Flag::Root)
+                && prompt::confirm("Does the plugin need to run as root?", false)?
+            {
+                flags.push(Flag::Root);
+            }
+
+            with_backend = with_backend
+                || prompt::confirm("Does the plugin need a custom backend (backend/)?", false)?;
+            with_py_modules = with_py_modules
+                || prompt::confirm(
+                    "Does the plugin bundle python modules (py_modules/)?",
+                    false,
+                )?;
+        }
+
+        let slug = Scaffolder::slugify(&name);
+        if slug.is_empty() {
+            return Err(anyhow!(
+                "Plugin name must contain at least one letter or digit"
+            ));
+        }
+
+        Ok(Self {
+            plugin_root: plugin_path.unwrap_or_else(|| PathBuf::from(slug)),
+            name,
+            author,
+            flags,
+            with_backend,
+            with_py_modules,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaffolds_a_loadable_plugin() {
+        let dir = tempfile::tempdir().unwrap();
+        let plugin_root = dir.path().join("hello");
+
+        let scaffolder = Scaffolder::new(
+            Some(plugin_root.clone()),
+            Some("Hello World".to_string()),
+            Some("Jane".to_string()),
+            vec![Flag::Root, Flag::Debug],
+            true,
+            true,
+        )
+        .unwrap();
+        scaffolder.run().unwrap();
+
+        let plugin = Plugin::new(plugin_root.clone()).unwrap();
+        assert_eq!(plugin.meta.name, "Hello World");
+        assert_eq!(plugin.meta.author, "Jane");
+        assert_eq!(plugin.meta.flags, vec![Flag::Root, Flag::Debug]);
+        assert!(matches!(
+            plugin.custom_backend,
+            crate::plugin::CustomBackend::Dockerfile
+        ));
+
+        let package: Value =
+            serde_json::from_str(&fs::read_to_string(plugin_root.join("package.json")).unwrap())
+                .unwrap();
+        assert_eq!(package["name"], "hello-world");
+        assert_eq!(
+            fs::read_dir(plugin_root.join("py_modules"))
+                .unwrap()
+                .count(),
+            0
+        );
+
+        assert!(scaffolder.run().is_err());
+    }
+}
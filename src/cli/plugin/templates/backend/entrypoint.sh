@@ -0,0 +1,8 @@
+#!/bin/sh
+set -e
+
+echo "Building backend"
+
+# Build your backend here. Everything written to /backend/out ends up in the
+# plugin's bin/ directory.
+mkdir -p /backend/out
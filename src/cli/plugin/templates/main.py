@@ -0,0 +1,19 @@
+import decky
+
+
+class Plugin:
+    # A normal method. It can be called from the frontend using @decky/api's `callable`
+    async def add(self, left: int, right: int) -> int:
+        return left + right
+
+    # Asyncio-compatible long-running code, executed in a task when the plugin is loaded
+    async def _main(self):
+        decky.logger.info("Plugin loaded")
+
+    # Function called first during the unload process, utilize this to handle your plugin being stopped
+    async def _unload(self):
+        decky.logger.info("Plugin unloaded")
+
+    # Function called after `_unload` during uninstall, utilize this to clean up processes and other remnants
+    async def _uninstall(self):
+        pass
@@ -0,0 +1,37 @@
+import { ButtonItem, PanelSection, PanelSectionRow, staticClasses } from "@decky/ui";
+import { callable, definePlugin, toaster } from "@decky/api";
+import { useState } from "react";
+import { FaPuzzlePiece } from "react-icons/fa";
+
+const pluginName = {{name_js}};
+
+// Calls `Plugin.add` in main.py
+const add = callable<[left: number, right: number], number>("add");
+
+function Content() {
+  const [result, setResult] = useState<number | undefined>();
+
+  const onClick = async () => {
+    const sum = await add(Math.random(), Math.random());
+    setResult(sum);
+    toaster.toast({ title: pluginName, body: `Result: ${sum}` });
+  };
+
+  return (
+    <PanelSection title="Panel Section">
+      <PanelSectionRow>
+        <ButtonItem layout="below" onClick={onClick}>
+          {result ?? "Add two numbers"}
+        </ButtonItem>
+      </PanelSectionRow>
+    </PanelSection>
+  );
+}
+
+export default definePlugin(() => ({
+  name: pluginName,
+  titleView: <div className={staticClasses.Title}>{pluginName}</div>,
+  content: <Content />,
+  icon: <FaPuzzlePiece />,
+  onDismount() {},
+}));
@@ -0,0 +1,818 @@
+use anyhow::{anyhow, Result};
+use log::info;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{cli::OutputFormat, plugin::Flag};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => f.write_str("error"),
+            Self::Warning => f.write_str("warning"),
+        }
+    }
+}
+
+/// A single problem found in a plugin, pointing at the file and field responsible
+#[derive(Serialize, Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.field {
+            Some(field) => write!(
+                f,
+                "{}: {}: {}: {}",
+                self.severity, self.file, field, self.message
+            ),
+            None => write!(f, "{}: {}: {}", self.severity, self.file, self.message),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    fn push(&mut self, severity: Severity, file: &str, field: Option<&str>, message: String) {
+        self.0.push(Diagnostic {
+            severity,
+            file: file.to_string(),
+            field: field.map(Into::into),
+            message,
+        });
+    }
+
+    pub fn error(&mut self, file: &str, field: Option<&str>, message: impl Into<String>) {
+        self.push(Severity::Error, file, field, message.into());
+    }
+
+    pub fn warning(&mut self, file: &str, field: Option<&str>, message: impl Into<String>) {
+        self.push(Severity::Warning, file, field, message.into());
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.0.iter().filter(|d| d.severity == severity).count()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0.iter()
+    }
+
+    /// Prints the diagnostics and fails if any of them is an error.
+    pub fn report(&self, format: &OutputFormat) -> Result<()> {
+        self.report_with(format, &Map::new())
+    }
+
+    /// The `--format json` report: the fields of `details`, the error and warning
+    /// counts, and every diagnostic.
+    fn json(&self, details: &impl Serialize) -> Result<Value> {
+        #[derive(Serialize)]
+        struct Report<'a, T: Serialize> {
+            #[serde(flatten)]
+            details: &'a T,
+            errors: usize,
+            warnings: usize,
+            diagnostics: &'a [Diagnostic],
+        }
+
+        Ok(serde_json::to_value(Report {
+            details,
+            errors: self.count(Severity::Error),
+            warnings: self.count(Severity::Warning),
+            diagnostics: &self.0,
+        })?)
+    }
+
+    /// Like [`Diagnostics::report`], but adds the fields of `details` to the JSON report.
+    pub fn report_with(&self, format: &OutputFormat, details: &impl Serialize) -> Result<()> {
+        let errors = self.count(Severity::Error);
+        let warnings = self.count(Severity::Warning);
+
+        match format {
+            OutputFormat::Text => {
+                for diagnostic in self.iter() {
+                    println!("{}", diagnostic);
+                }
+                println!("{} error(s), {} warning(s)", errors, warnings);
+            }
+            OutputFormat::Json => {
+                println!("{}", serde_json::to_string_pretty(&self.json(details)?)?)
+            }
+        }
+
+        match errors {
+            0 => Ok(()),
+            _ => Err(anyhow!("Found {} error(s)", errors)),
+        }
+    }
+}
+
+pub struct Validator {
+    pub plugin_root: PathBuf,
+    pub format: OutputFormat,
+}
+
+impl Validator {
+    fn read_json(&self, file: &str, diagnostics: &mut Diagnostics) -> Option<Map<String, Value>> {
+        let path = self.plugin_root.join(file);
+
+        if !path.exists() {
+            diagnostics.error(file, None, "file is missing");
+            return None;
+        }
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                diagnostics.error(file, None, format!("could not be read: {}", err));
+                return None;
+            }
+        };
+
+        match serde_json::from_str::<Value>(&contents) {
+            Ok(Value::Object(map)) => Some(map),
+            Ok(_) => {
+                diagnostics.error(file, None, "expected a JSON object at the top level");
+                None
+            }
+            Err(err) => {
+                diagnostics.error(file, None, format!("invalid JSON: {}", err));
+                None
+            }
+        }
+    }
+
+    fn check_string(
+        object: &Map<String, Value>,
+        file: &str,
+        field: &str,
+        required: bool,
+        diagnostics: &mut Diagnostics,
+    ) -> Option<String> {
+        match object.get(field) {
+            Some(Value::String(value)) if value.trim().is_empty() && required => {
+                diagnostics.error(file, Some(field), "must not be empty");
+                None
+            }
+            Some(Value::String(value)) => Some(value.clone()),
+            Some(_) => {
+                diagnostics.error(file, Some(field), "expected a string");
+                None
+            }
+            None if required => {
+                diagnostics.error(file, Some(field), "is required");
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn check_pluginfile(&self, diagnostics: &mut Diagnostics) {
+        const FILE: &str = "plugin.json";
+
+        let Some(plugin) = self.read_json(FILE, diagnostics) else {
+            return;
+        };
+
+        Validator::check_string(&plugin, FILE, "name", true, diagnostics);
+        Validator::check_string(&plugin, FILE, "author", true, diagnostics);
+
+        match plugin.get("flags") {
+            Some(Value::Array(flags)) => {
+                for (index, flag) in flags.iter().enumerate() {
+                    let field = format!("flags[{}]", index);
+                    match flag {
+                        Value::String(flag) => {
+                            if let Err(err) = flag.parse::<Flag>() {
+                                diagnostics.error(FILE, Some(&field), err);
+                            }
+                        }
+                        _ => diagnostics.error(FILE, Some(&field), "expected a string"),
+                    }
+                }
+            }
+            Some(_) => diagnostics.error(FILE, Some("flags"), "expected an array"),
+            None => diagnostics.error(FILE, Some("flags"), "is required (use [] for none)"),
+        }
+
+        match plugin.get("publish") {
+            Some(Value::Object(publish)) => {
+                match publish.get("tags") {
+                    Some(Value::Array(tags)) => {
+                        for (index, tag) in tags.iter().enumerate() {
+                            if !tag.is_string() {
+                                diagnostics.error(
+                                    FILE,
+                                    Some(&format!("publish.tags[{}]", index)),
+                                    "expected a string",
+                                );
+                            }
+                        }
+                    }
+                    Some(_) => diagnostics.error(FILE, Some("publish.tags"), "expected an array"),
+                    None => diagnostics.warning(FILE, Some("publish.tags"), "is missing"),
+                }
+
+                match publish.get("description") {
+                    Some(Value::String(description)) if description.trim().is_empty() => {
+                        diagnostics.warning(FILE, Some("publish.description"), "is empty")
+                    }
+                    Some(Value::String(_)) => {}
+                    Some(_) => {
+                        diagnostics.error(FILE, Some("publish.description"), "expected a string")
+                    }
+                    None => diagnostics.warning(FILE, Some("publish.description"), "is missing"),
+                }
+
+                match publish.get("image") {
+                    Some(Value::String(image)) if image.trim().is_empty() => {
+                        diagnostics.warning(FILE, Some("publish.image"), "is empty")
+                    }
+                    Some(Value::String(image))
+                        if !image.starts_with("https://") && !image.starts_with("http://") =>
+                    {
+                        diagnostics.warning(FILE, Some("publish.image"), "should be an http(s) URL")
+                    }
+                    Some(Value::String(_)) => {}
+                    Some(_) => diagnostics.error(FILE, Some("publish.image"), "expected a string"),
+                    None => diagnostics.warning(FILE, Some("publish.image"), "is missing"),
+                }
+            }
+            Some(_) => diagnostics.error(FILE, Some("publish"), "expected an object"),
+            None => diagnostics.warning(
+                FILE,
+                Some("publish"),
+                "is missing, the plugin cannot be submitted to the store without it",
+            ),
+        }
+    }
+
+    fn is_semver(version: &str) -> bool {
+        let core = version.split(['-', '+']).next().unwrap_or_default();
+        let parts = core.split('.').collect::<Vec<_>>();
+
+        parts.len() == 3
+            && parts
+                .iter()
+                .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+    }
+
+    fn check_remote_binary(entry: &Value, index: usize, diagnostics: &mut Diagnostics) {
+        const FILE: &str = "package.json";
+        let field = |name: &str| format!("remote_binary[{}].{}", index, name);
+
+        let Value::Object(entry) = entry else {
+            diagnostics.error(
+                FILE,
+                Some(&format!("remote_binary[{}]", index)),
+                "expected an object",
+            );
+            return;
+        };
+
+        for name in ["url", "sha256hash", "name"] {
+            match entry.get(name) {
+                Some(Value::String(_)) => {}
+                Some(_) => diagnostics.error(FILE, Some(&field(name)), "expected a string"),
+                None => diagnostics.error(FILE, Some(&field(name)), "is required"),
+            }
+        }
+
+        if let Some(Value::String(url)) = entry.get("url") {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                diagnostics.error(FILE, Some(&field("url")), "must be an http(s) URL");
+            }
+        }
+
+        if let Some(Value::String(hash)) = entry.get("sha256hash") {
+            if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                diagnostics.error(
+                    FILE,
+                    Some(&field("sha256hash")),
+                    "must be a 64 character hex encoded sha256 digest",
+                );
+            }
+        }
+
+        if let Some(Value::String(name)) = entry.get("name") {
+            if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+                diagnostics.error(
+                    FILE,
+                    Some(&field("name")),
+                    "must be a plain file name, it is written to bin/",
+                );
+            }
+        }
+    }
+
+    fn check_package_json(&self, diagnostics: &mut Diagnostics) {
+        const FILE: &str = "package.json";
+
+        let Some(package) = self.read_json(FILE, diagnostics) else {
+            return;
+        };
+
+        Validator::check_string(&package, FILE, "name", true, diagnostics);
+
+        if let Some(version) = Validator::check_string(&package, FILE, "version", true, diagnostics)
+        {
+            if !Validator::is_semver(&version) {
+                diagnostics.warning(
+                    FILE,
+                    Some("version"),
+                    format!("`{}` is not a semantic version (x.y.z)", version),
+                );
+            }
+        }
+
+        let bundling = match package.get("remote_binary_bundling") {
+            Some(Value::Bool(bundling)) => *bundling,
+            Some(_) => {
+                diagnostics.error(FILE, Some("remote_binary_bundling"), "expected a boolean");
+                false
+            }
+            None => false,
+        };
+
+        match package.get("remote_binary") {
+            Some(Value::Array(entries)) => {
+                for (index, entry) in entries.iter().enumerate() {
+                    Validator::check_remote_binary(entry, index, diagnostics);
+                }
+
+                if !entries.is_empty() && !bundling {
+                    diagnostics.warning(
+                        FILE,
+                        Some("remote_binary_bundling"),
+                        "remote_binary entries are listed but will not be bundled unless this is true",
+                    );
+                }
+            }
+            Some(_) => diagnostics.error(FILE, Some("remote_binary"), "expected an array"),
+            None => {}
+        }
+    }
+
+    fn check_files(&self, diagnostics: &mut Diagnostics) {
+        for file in ["LICENSE", "README.md"] {
+            if !self.plugin_root.join(file).is_file() {
+                diagnostics.error(
+                    file,
+                    None,
+                    "file is missing, it is required in the plugin zip",
+                );
+            }
+        }
+
+        if !self.plugin_root.join("main.py").is_file() {
+            diagnostics.error(
+                "main.py",
+                None,
+                "file is missing, Decky Loader needs it to load the plugin",
+            );
+        }
+    }
+
+    fn check_backend(&self, diagnostics: &mut Diagnostics) {
+        let backend: &Path = &self.plugin_root.join("backend");
+
+        if !backend.exists() {
+            return;
+        }
+
+        let dockerfile = backend.join("Dockerfile");
+        if !dockerfile.is_file() {
+            diagnostics.error(
+                "backend/Dockerfile",
+                None,
+                "backend directory found, but it has no Dockerfile. Remove `backend` if the plugin has no custom backend",
+            );
+            return;
+        }
+
+        let entrypoint = backend.join("entrypoint.sh");
+        let dockerfile_has_entrypoint = fs::read_to_string(&dockerfile)
+            .map(|contents| {
+                contents.lines().any(|line| {
+                    line.trim_start()
+                        .to_ascii_uppercase()
+                        .starts_with("ENTRYPOINT")
+                })
+            })
+            .unwrap_or(false);
+
+        if !entrypoint.is_file() && !dockerfile_has_entrypoint {
+            diagnostics.warning(
+                "backend/Dockerfile",
+                None,
+                "no ENTRYPOINT and no backend/entrypoint.sh, the backend container will not build anything",
+            );
+        }
+
+        #[cfg(target_family = "unix")]
+        if let Ok(metadata) = entrypoint.metadata() {
+            use std::os::unix::fs::PermissionsExt;
+
+            if metadata.permissions().mode() & 0o111 == 0 {
+                diagnostics.warning("backend/entrypoint.sh", None, "is not executable");
+            }
+        }
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        let mut diagnostics = Diagnostics::default();
+
+        self.check_pluginfile(&mut diagnostics);
+        self.check_package_json(&mut diagnostics);
+        self.check_files(&mut diagnostics);
+        self.check_backend(&mut diagnostics);
+
+        diagnostics
+    }
+
+    pub fn run(&self) -> Result<()> {
+        info!("Validating {}", self.plugin_root.display());
+        self.diagnostics().report(&self.format)
+    }
+
+    pub fn new(plugin_root: PathBuf, format: OutputFormat) -> Result<Self> {
+        if !plugin_root.is_dir() {
+            return Err(anyhow!("{} is not a directory", plugin_root.display()));
+        }
+
+        Ok(Self {
+            plugin_root,
+            format,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A plugin that passes every check, changed by `setup` before validating it
+    fn validate(setup: impl FnOnce(&Path)) -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+
+        write_json(
+            root,
+            "plugin.json",
+            json!({
+                "name": "Example",
+                "author": "Jane",
+                "flags": ["debug"],
+                "publish": {
+                    "tags": ["example"],
+                    "description": "An example plugin",
+                    "image": "https://example.com/image.png"
+                }
+            }),
+        );
+        write_json(
+            root,
+            "package.json",
+            json!({ "name": "example", "version": "1.0.0" }),
+        );
+        for file in ["LICENSE", "README.md", "main.py"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        setup(root);
+
+        Validator::new(root.to_path_buf(), OutputFormat::Text)
+            .unwrap()
+            .diagnostics()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    fn write_json(root: &Path, file: &str, value: Value) {
+        fs::write(root.join(file), value.to_string()).unwrap();
+    }
+
+    /// Sets `field` of the JSON `file` to `value`, or removes it if `value` is null.
+    fn set(root: &Path, file: &str, field: &str, value: Value) {
+        let path = root.join(file);
+        let mut json: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let (parent, name) = match field.split_once('.') {
+            Some((parent, name)) => (&mut json[parent], name),
+            None => (&mut json, field),
+        };
+        match value {
+            Value::Null => {
+                parent.as_object_mut().unwrap().remove(name);
+            }
+            value => parent[name] = value,
+        }
+        write_json(root, file, json);
+    }
+
+    #[test]
+    fn accepts_valid_plugin() {
+        assert_eq!(validate(|_| {}), Vec::<String>::new());
+    }
+
+    #[test]
+    fn checks_pluginfile() {
+        let check =
+            |field: &str, value: Value| validate(|root| set(root, "plugin.json", field, value));
+
+        assert_eq!(
+            validate(|root| fs::remove_file(root.join("plugin.json")).unwrap()),
+            ["error: plugin.json: file is missing"]
+        );
+        assert_eq!(
+            validate(|root| fs::write(root.join("plugin.json"), "{").unwrap())[0],
+            "error: plugin.json: invalid JSON: EOF while parsing an object at line 1 column 1"
+        );
+        assert_eq!(
+            validate(|root| fs::write(root.join("plugin.json"), "[]").unwrap()),
+            ["error: plugin.json: expected a JSON object at the top level"]
+        );
+
+        assert_eq!(
+            check("name", json!(" ")),
+            ["error: plugin.json: name: must not be empty"]
+        );
+        assert_eq!(
+            check("author", Value::Null),
+            ["error: plugin.json: author: is required"]
+        );
+        assert_eq!(
+            check("author", json!(1)),
+            ["error: plugin.json: author: expected a string"]
+        );
+
+        assert_eq!(
+            check("flags", json!(["root", "rooot", 1])),
+            [
+                "error: plugin.json: flags[1]: unknown flag `rooot`, valid flags are: root, _root, debug",
+                "error: plugin.json: flags[2]: expected a string"
+            ]
+        );
+        assert_eq!(
+            check("flags", json!("root")),
+            ["error: plugin.json: flags: expected an array"]
+        );
+        assert_eq!(
+            check("flags", Value::Null),
+            ["error: plugin.json: flags: is required (use [] for none)"]
+        );
+    }
+
+    #[test]
+    fn checks_publish_section() {
+        let check =
+            |field: &str, value: Value| validate(|root| set(root, "plugin.json", field, value));
+
+        assert_eq!(
+            check("publish", Value::Null),
+            ["warning: plugin.json: publish: is missing, the plugin cannot be submitted to the store without it"]
+        );
+        assert_eq!(
+            check("publish", json!([])),
+            ["error: plugin.json: publish: expected an object"]
+        );
+
+        assert_eq!(
+            check("publish.tags", Value::Null),
+            ["warning: plugin.json: publish.tags: is missing"]
+        );
+        assert_eq!(
+            check("publish.tags", json!("example")),
+            ["error: plugin.json: publish.tags: expected an array"]
+        );
+        assert_eq!(
+            check("publish.tags", json!(["example", 2])),
+            ["error: plugin.json: publish.tags[1]: expected a string"]
+        );
+
+        assert_eq!(
+            check("publish.description", json!("")),
+            ["warning: plugin.json: publish.description: is empty"]
+        );
+        assert_eq!(
+            check("publish.description", json!(false)),
+            ["error: plugin.json: publish.description: expected a string"]
+        );
+        assert_eq!(
+            check("publish.description", Value::Null),
+            ["warning: plugin.json: publish.description: is missing"]
+        );
+
+        assert_eq!(
+            check("publish.image", json!("")),
+            ["warning: plugin.json: publish.image: is empty"]
+        );
+        assert_eq!(
+            check("publish.image", json!("image.png")),
+            ["warning: plugin.json: publish.image: should be an http(s) URL"]
+        );
+        assert_eq!(
+            check("publish.image", json!({})),
+            ["error: plugin.json: publish.image: expected a string"]
+        );
+        assert_eq!(
+            check("publish.image", Value::Null),
+            ["warning: plugin.json: publish.image: is missing"]
+        );
+    }
+
+    #[test]
+    fn checks_package_json() {
+        let check =
+            |field: &str, value: Value| validate(|root| set(root, "package.json", field, value));
+
+        assert_eq!(
+            validate(|root| fs::remove_file(root.join("package.json")).unwrap()),
+            ["error: package.json: file is missing"]
+        );
+        assert_eq!(
+            check("name", Value::Null),
+            ["error: package.json: name: is required"]
+        );
+        assert_eq!(
+            check("version", json!("1.0")),
+            ["warning: package.json: version: `1.0` is not a semantic version (x.y.z)"]
+        );
+        assert_eq!(
+            check("version", json!("1.2.3-beta.1+build")),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            check("remote_binary_bundling", json!("yes")),
+            ["error: package.json: remote_binary_bundling: expected a boolean"]
+        );
+        assert_eq!(
+            check("remote_binary", json!({})),
+            ["error: package.json: remote_binary: expected an array"]
+        );
+    }
+
+    #[test]
+    fn checks_remote_binaries() {
+        let check = |bundling: bool, entries: Value| {
+            validate(|root| {
+                set(
+                    root,
+                    "package.json",
+                    "remote_binary_bundling",
+                    json!(bundling),
+                );
+                set(root, "package.json", "remote_binary", entries);
+            })
+        };
+        let valid = json!({
+            "url": "https://example.com/tool",
+            "sha256hash": "a".repeat(64),
+            "name": "tool"
+        });
+
+        assert_eq!(check(true, json!([valid])), Vec::<String>::new());
+        assert_eq!(
+            check(false, json!([valid])),
+            ["warning: package.json: remote_binary_bundling: remote_binary entries are listed but will not be bundled unless this is true"]
+        );
+        assert_eq!(
+            check(true, json!(["tool"])),
+            ["error: package.json: remote_binary[0]: expected an object"]
+        );
+        assert_eq!(
+            check(true, json!([{ "url": 1 }])),
+            [
+                "error: package.json: remote_binary[0].url: expected a string",
+                "error: package.json: remote_binary[0].sha256hash: is required",
+                "error: package.json: remote_binary[0].name: is required"
+            ]
+        );
+        assert_eq!(
+            check(
+                true,
+                json!([{ "url": "ftp://example.com/tool", "sha256hash": "abc", "name": "../tool" }])
+            ),
+            [
+                "error: package.json: remote_binary[0].url: must be an http(s) URL",
+                "error: package.json: remote_binary[0].sha256hash: must be a 64 character hex encoded sha256 digest",
+                "error: package.json: remote_binary[0].name: must be a plain file name, it is written to bin/"
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_files() {
+        assert_eq!(
+            validate(|root| {
+                for file in ["LICENSE", "README.md", "main.py"] {
+                    fs::remove_file(root.join(file)).unwrap();
+                }
+            }),
+            [
+                "error: LICENSE: file is missing, it is required in the plugin zip",
+                "error: README.md: file is missing, it is required in the plugin zip",
+                "error: main.py: file is missing, Decky Loader needs it to load the plugin"
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_backend() {
+        let backend = |dockerfile: Option<&str>, entrypoint: Option<u32>| {
+            validate(|root| {
+                let dir = root.join("backend");
+                fs::create_dir(&dir).unwrap();
+                if let Some(dockerfile) = dockerfile {
+                    fs::write(dir.join("Dockerfile"), dockerfile).unwrap();
+                }
+                if let Some(_mode) = entrypoint {
+                    fs::write(dir.join("entrypoint.sh"), "").unwrap();
+                    #[cfg(target_family = "unix")]
+                    fs::set_permissions(
+                        dir.join("entrypoint.sh"),
+                        std::os::unix::fs::PermissionsExt::from_mode(_mode),
+                    )
+                    .unwrap();
+                }
+            })
+        };
+
+        assert_eq!(
+            backend(None, None),
+            ["error: backend/Dockerfile: backend directory found, but it has no Dockerfile. Remove `backend` if the plugin has no custom backend"]
+        );
+        assert_eq!(
+            backend(Some("FROM alpine"), None),
+            ["warning: backend/Dockerfile: no ENTRYPOINT and no backend/entrypoint.sh, the backend container will not build anything"]
+        );
+        assert_eq!(
+            backend(Some("FROM alpine\n  entrypoint [\"make\"]"), None),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            backend(Some("FROM alpine"), Some(0o755)),
+            Vec::<String>::new()
+        );
+        #[cfg(target_family = "unix")]
+        assert_eq!(
+            backend(Some("FROM alpine"), Some(0o644)),
+            ["warning: backend/entrypoint.sh: is not executable"]
+        );
+    }
+
+    #[test]
+    fn reports_json() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.error("plugin.json", Some("name"), "is required");
+        diagnostics.warning("README.md", None, "file is missing");
+
+        assert_eq!(
+            diagnostics.json(&json!({ "root": "example" })).unwrap(),
+            json!({
+                "root": "example",
+                "errors": 1,
+                "warnings": 1,
+                "diagnostics": [
+                    {
+                        "severity": "error",
+                        "file": "plugin.json",
+                        "field": "name",
+                        "message": "is required"
+                    },
+                    {
+                        "severity": "warning",
+                        "file": "README.md",
+                        "message": "file is missing"
+                    }
+                ]
+            })
+        );
+        let keys = diagnostics.json(&Map::new()).unwrap();
+        assert_eq!(
+            keys.as_object().unwrap().keys().collect::<Vec<_>>(),
+            ["diagnostics", "errors", "warnings"]
+        );
+        assert!(diagnostics.report(&OutputFormat::Json).is_err());
+    }
+}
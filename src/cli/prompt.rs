@@ -0,0 +1,60 @@
+use anyhow::{anyhow, Result};
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// Whether we can ask the user questions on the terminal.
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+/// Asks for a line of text, falling back to `default` when the answer is empty.
+pub fn text(question: &str, default: Option<&str>) -> Result<String> {
+    loop {
+        match default {
+            Some(default) => print!("{question} [{default}]: "),
+            None => print!("{question}: "),
+        }
+        io::stdout().flush()?;
+
+        let answer = read_line()?;
+        let answer = answer.trim();
+
+        match (answer.is_empty(), default) {
+            (false, _) => return Ok(answer.to_string()),
+            (true, Some(default)) => return Ok(default.to_string()),
+            (true, None) => println!("A value is required."),
+        }
+    }
+}
+
+/// Asks a yes/no question.
+pub fn confirm(question: &str, default: bool) -> Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+
+    loop {
+        print!("{question} [{hint}]: ");
+        io::stdout().flush()?;
+
+        match read_line()?.trim().to_ascii_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("Please answer `y` or `n`."),
+        }
+    }
+}
+
+fn read_line() -> Result<String> {
+    let mut line = String::new();
+    let read = io::stdin().lock().read_line(&mut line)?;
+
+    if read == 0 {
+        return Err(anyhow!("Reached end of input while waiting for an answer"));
+    }
+
+    Ok(line)
+}
+
+/// Asks for a secret without echoing it. May be empty.
+pub fn password(question: &str) -> Result<String> {
+    Ok(rpassword::prompt_password(format!("{question}: "))?)
+}
@@ -0,0 +1,280 @@
+use anyhow::{anyhow, Context, Result};
+use glob::Pattern;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+/// Optional `decky.toml` in the plugin root, holding settings for this CLI
+/// that don't belong in plugin.json
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub build: BuildConfig,
+    pub zip: ZipConfig,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuildConfig {
+    /// Image used to build the frontend, optionally pinned to a tag or digest
+    pub builder_image: Option<String>,
+}
+
+/// What goes into the plugin zip besides the files every plugin has
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ZipConfig {
+    /// Extra files and directories from the plugin root, as globs
+    pub include: Vec<String>,
+    /// Paths inside the plugin's directory in the zip to leave out, as globs
+    pub exclude: Vec<String>,
+    /// Unix permissions for everything inside a directory of the zip
+    pub permissions: BTreeMap<String, u32>,
+    /// Largest the zip may get before the build fails, e.g. `10 MiB`
+    pub max_size: Option<String>,
+}
+
+impl ZipConfig {
+    /// File in the plugin root with more `exclude` globs, one per line
+    pub const IGNORE_FILENAME: &'static str = ".deckyignore";
+
+    /// Checks the globs and adds the ones from `.deckyignore`.
+    pub fn rules(&self, plugin_root: &Path) -> Result<ZipRules> {
+        let mut exclude = self.exclude.clone();
+
+        let ignore_file = plugin_root.join(Self::IGNORE_FILENAME);
+        if ignore_file.exists() {
+            let contents = fs::read_to_string(&ignore_file)
+                .with_context(|| format!("Could not read {}", ignore_file.display()))?;
+            exclude.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(String::from),
+            );
+        }
+
+        let include = self
+            .include
+            .iter()
+            .map(|glob| relative_glob(glob).map(|_| glob.clone()))
+            .collect::<Result<_>>()?;
+
+        let exclude = exclude
+            .iter()
+            .map(|glob| {
+                relative_glob(glob).and_then(|glob| {
+                    Pattern::new(glob.trim_end_matches('/'))
+                        .with_context(|| format!("Invalid exclude glob `{}`", glob))
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let permissions = self
+            .permissions
+            .iter()
+            .map(|(dir, mode)| match *mode <= 0o777 {
+                true => relative_glob(dir).map(|dir| (PathBuf::from(dir), *mode)),
+                false => Err(anyhow!("Invalid permissions {:o} for `{}`", mode, dir)),
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(ZipRules {
+            include,
+            exclude,
+            permissions,
+        })
+    }
+}
+
+/// Makes sure `glob` stays inside the directory it is relative to.
+fn relative_glob(glob: &str) -> Result<&str> {
+    let escapes = Path::new(glob)
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+
+    match escapes {
+        true => Err(anyhow!("`{}` must be relative to the plugin", glob)),
+        false => Ok(glob),
+    }
+}
+
+/// Checked form of [`ZipConfig`], used while zipping
+#[derive(Clone, Debug, Default)]
+pub struct ZipRules {
+    include: Vec<String>,
+    exclude: Vec<Pattern>,
+    permissions: Vec<(PathBuf, u32)>,
+}
+
+impl ZipRules {
+    /// Paths in `plugin_root` matched by the `include` globs, in order
+    pub fn included(&self, plugin_root: &Path) -> Result<Vec<PathBuf>> {
+        let root = Pattern::escape(&plugin_root.to_string_lossy());
+        let mut paths = vec![];
+
+        for include in &self.include {
+            let matches = glob::glob(&format!("{}/{}", root, include))
+                .with_context(|| format!("Invalid include glob `{}`", include))?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if matches.is_empty() {
+                warn!("`{}` does not match any files", include);
+            }
+
+            paths.extend(matches);
+        }
+
+        Ok(paths)
+    }
+
+    /// Whether `name`, relative to the plugin's directory in the zip, or one of
+    /// the directories containing it is excluded
+    pub fn is_excluded(&self, name: &Path) -> bool {
+        name.ancestors()
+            .filter(|path| !path.as_os_str().is_empty())
+            .any(|path| self.exclude.iter().any(|glob| glob.matches_path(path)))
+    }
+
+    /// Permissions of the innermost directory containing `name` that has them set
+    pub fn mode(&self, name: &Path) -> Option<u32> {
+        self.permissions
+            .iter()
+            .filter(|(dir, _)| name.starts_with(dir))
+            .max_by_key(|(dir, _)| dir.components().count())
+            .map(|(_, mode)| *mode)
+    }
+}
+
+impl ProjectConfig {
+    pub const FILENAME: &'static str = "decky.toml";
+
+    pub fn load(plugin_root: &Path) -> Result<Self> {
+        let location = plugin_root.join(ProjectConfig::FILENAME);
+
+        if !location.exists() {
+            return Ok(Self::default());
+        }
+
+        info!("Loading {}...", ProjectConfig::FILENAME);
+        std::fs::read_to_string(&location)
+            .map_err(anyhow::Error::from)
+            .and_then(|str| toml::from_str(&str).map_err(Into::into))
+            .with_context(|| format!("Could not parse {}", location.display()))
+    }
+}
+
+/// A named Deck in `decks.toml`. Fields that are not set come from deck.json.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeckProfile {
+    pub ip: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    /// Private key to log in with
+    pub key: Option<PathBuf>,
+    /// Home directory that holds `homebrew/`
+    pub dir: Option<String>,
+}
+
+/// The user's Decks, kept in `~/.config/decky/decks.toml` so every plugin can deploy to them
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeckProfiles {
+    pub deck: BTreeMap<String, DeckProfile>,
+}
+
+impl DeckProfiles {
+    pub const FILENAME: &'static str = "decks.toml";
+
+    pub fn default_location() -> Result<PathBuf> {
+        dirs::config_dir()
+            .map(|dir| dir.join("decky").join(Self::FILENAME))
+            .ok_or_else(|| anyhow!("Could not determine the user config directory"))
+    }
+
+    pub fn load(location: &Path) -> Result<Self> {
+        if !location.exists() {
+            return Ok(Self::default());
+        }
+
+        fs::read_to_string(location)
+            .map_err(anyhow::Error::from)
+            .and_then(|str| toml::from_str(&str).map_err(Into::into))
+            .with_context(|| format!("Could not parse {}", location.display()))
+    }
+
+    pub fn save(&self, location: &Path) -> Result<()> {
+        if let Some(dir) = location.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(location, toml::to_string_pretty(self)?)
+            .with_context(|| format!("Could not write {}", location.display()))
+    }
+
+    pub fn get(&self, name: &str) -> Result<&DeckProfile> {
+        self.deck.get(name).ok_or_else(|| {
+            anyhow!(
+                "No Deck named `{}`. Add it with `decky deck add {} --ip <ip>`",
+                name,
+                name
+            )
+        })
+    }
+}
+
+/// Deck passwords, kept out of plugin repositories in `~/.config/decky/secrets.toml`
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeckSecrets {
+    /// sudo passwords by `user@host`
+    pub passwords: BTreeMap<String, String>,
+}
+
+impl DeckSecrets {
+    pub const FILENAME: &'static str = "secrets.toml";
+
+    pub fn default_location() -> Result<PathBuf> {
+        dirs::config_dir()
+            .map(|dir| dir.join("decky").join(Self::FILENAME))
+            .ok_or_else(|| anyhow!("Could not determine the user config directory"))
+    }
+
+    pub fn load(location: &Path) -> Result<Self> {
+        if !location.exists() {
+            return Ok(Self::default());
+        }
+
+        fs::read_to_string(location)
+            .map_err(anyhow::Error::from)
+            .and_then(|str| toml::from_str(&str).map_err(Into::into))
+            .with_context(|| format!("Could not parse {}", location.display()))
+    }
+
+    /// Writes the secrets so only the current user can read them.
+    pub fn save(&self, location: &Path) -> Result<()> {
+        if let Some(dir) = location.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options
+            .open(location)
+            .with_context(|| format!("Could not write {}", location.display()))?;
+        #[cfg(target_family = "unix")]
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+        std::io::Write::write_all(&mut file, toml::to_string_pretty(self)?.as_bytes())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,620 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use bollard::{
+    container::{
+        Config, CreateContainerOptions, LogOutput, LogsOptions, RemoveContainerOptions,
+        WaitContainerOptions,
+    },
+    errors::Error as DockerError,
+    image::{BuildImageOptions, CreateImageOptions},
+    models::{BuildInfo, ContainerWaitResponse, CreateImageInfo, HostConfig},
+    Docker, API_DEFAULT_VERSION,
+};
+use futures::StreamExt;
+use log::{debug, info, warn};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+use tokio::sync::OnceCell;
+
+use super::{pick_digest, prepare_binds, Engine, RunOptions, UserMapping};
+use crate::{
+    cli::ContainerEngine,
+    output::{StageLog, Stream},
+};
+
+/// Containers we started and have not removed yet, so they can be cleaned up
+/// if the CLI is interrupted.
+static RUNNING: Mutex<Vec<(Docker, String)>> = Mutex::new(Vec::new());
+
+/// The list of running containers, even if a thread panicked while holding it
+fn running() -> MutexGuard<'static, Vec<(Docker, String)>> {
+    RUNNING.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub async fn remove_running_containers() {
+    let running = std::mem::take(&mut *running());
+
+    for (docker, id) in running {
+        info!("Removing container {}", id);
+        if let Err(err) = docker
+            .remove_container(
+                &id,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+        {
+            warn!("Could not remove container {}: {}", id, err);
+        }
+    }
+}
+
+/// Splits chunks of container output into lines
+struct LineBuffer<'a> {
+    log: &'a StageLog,
+    stream: Stream,
+    buffer: String,
+}
+
+impl<'a> LineBuffer<'a> {
+    fn new(log: &'a StageLog, stream: Stream) -> Self {
+        Self {
+            log,
+            stream,
+            buffer: String::new(),
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        self.buffer.push_str(&String::from_utf8_lossy(chunk));
+
+        while let Some(newline) = self.buffer.find('\n') {
+            let line: String = self.buffer.drain(..=newline).collect();
+            self.log
+                .line(self.stream, line.trim_end_matches(['\r', '\n']));
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.buffer.is_empty() {
+            self.log
+                .line(self.stream, &std::mem::take(&mut self.buffer));
+        }
+    }
+}
+
+/// Talks to docker or podman through the Docker-compatible REST API on their
+/// unix socket
+pub struct ApiEngine {
+    docker: Docker,
+    description: String,
+    kind: ContainerEngine,
+    user_mapping: OnceCell<UserMapping>,
+}
+
+impl ApiEngine {
+    /// Places the engine's API socket is usually found, most specific first.
+    fn socket_candidates(engine: &ContainerEngine) -> Vec<PathBuf> {
+        let home = dirs::home_dir().unwrap_or_default();
+        let from_env = |var: &str| {
+            env::var(var)
+                .ok()
+                .and_then(|host| host.strip_prefix("unix://").map(PathBuf::from))
+        };
+
+        match engine {
+            ContainerEngine::Docker => vec![
+                from_env("DOCKER_HOST"),
+                Some(PathBuf::from("/var/run/docker.sock")),
+                Some(home.join(".docker/run/docker.sock")),
+                Some(home.join(".docker/desktop/docker.sock")),
+                Some(home.join(".colima/default/docker.sock")),
+                Some(home.join(".colima/docker.sock")),
+                Some(home.join(".lima/docker/sock/docker.sock")),
+                Some(home.join(".rd/docker.sock")),
+            ],
+            ContainerEngine::Podman => vec![
+                from_env("CONTAINER_HOST"),
+                env::var("XDG_RUNTIME_DIR")
+                    .ok()
+                    .map(|dir| Path::new(&dir).join("podman/podman.sock")),
+                Some(PathBuf::from("/run/podman/podman.sock")),
+                Some(home.join(".local/share/containers/podman/machine/podman.sock")),
+            ],
+            ContainerEngine::Nerdctl | ContainerEngine::Auto | ContainerEngine::Native => {
+                vec![]
+            }
+        }
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    pub fn connect(engine: &ContainerEngine) -> Result<Self> {
+        let candidates = ApiEngine::socket_candidates(engine);
+        if candidates.is_empty() {
+            return Err(anyhow!(
+                "{} does not provide a Docker-compatible API socket",
+                engine.bin_name()
+            ));
+        }
+
+        let socket = candidates
+            .iter()
+            .find(|socket| socket.exists())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Could not find the {} API socket, looked in: {}",
+                    engine.bin_name(),
+                    candidates
+                        .iter()
+                        .map(|socket| socket.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+
+        debug!("Connecting to {}", socket.display());
+        let docker = Docker::connect_with_unix(&socket.to_string_lossy(), 600, API_DEFAULT_VERSION)
+            .with_context(|| format!("Could not connect to {}", socket.display()))?;
+
+        Ok(Self {
+            docker,
+            description: format!("{} (API at {})", engine.bin_name(), socket.display()),
+            kind: engine.clone(),
+            user_mapping: OnceCell::new(),
+        })
+    }
+
+    async fn user_mapping(&self) -> Result<UserMapping> {
+        self.user_mapping
+            .get_or_try_init(|| async {
+                let info = self.docker.info().await?;
+                let rootless = info
+                    .security_options
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|option| option.contains("rootless"));
+                let mapping = UserMapping::from_rootless(&self.kind, rootless);
+                debug!("{} user mapping: {:?}", self.description, mapping);
+                Ok(mapping)
+            })
+            .await
+            .copied()
+    }
+
+    /// Packs the Dockerfile's directory into a tarball to send as build context,
+    /// returning it with the Dockerfile's name inside it.
+    fn build_context(dockerfile: &Path) -> Result<(Vec<u8>, String)> {
+        let directory = dockerfile
+            .parent()
+            .ok_or_else(|| anyhow!("{} has no parent directory", dockerfile.display()))?;
+        let name = dockerfile
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("{} is not a valid Dockerfile name", dockerfile.display()))?;
+
+        let mut archive = tar::Builder::new(Vec::new());
+        archive.follow_symlinks(true);
+        archive.append_dir_all(".", directory)?;
+        Ok((archive.into_inner()?, name.to_string()))
+    }
+
+    /// Logs a progress message of `docker pull`, failing on errors.
+    fn log_pull_progress(image: &str, info: CreateImageInfo, log: &StageLog) -> Result<()> {
+        if let Some(error) = info.error {
+            return Err(anyhow!("Failed to pull {}: {}", image, error));
+        }
+
+        match (info.id, info.status) {
+            (Some(id), Some(status)) if info.progress.is_none() => {
+                log.line(Stream::Stdout, &format!("{}: {}", id, status))
+            }
+            (None, Some(status)) => log.line(Stream::Stdout, &status),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Logs a progress message of `docker build`, failing on errors.
+    fn log_build_progress(info: BuildInfo, log: &StageLog) -> Result<()> {
+        if let Some(error) = info.error {
+            return Err(anyhow!("Image build failed: {}", error));
+        }
+
+        if let Some(stream) = info.stream {
+            for line in stream.lines().filter(|line| !line.trim().is_empty()) {
+                log.line(Stream::Stdout, line);
+            }
+        } else if let (Some(status), None) = (info.status, info.progress) {
+            log.line(Stream::Stdout, &status);
+        }
+
+        Ok(())
+    }
+
+    /// Turns a response of waiting for a container into its exit status.
+    fn check_exit(response: Result<ContainerWaitResponse, DockerError>) -> Result<()> {
+        match response {
+            Ok(response) if response.status_code != 0 => Err(anyhow!(
+                "container exited with status {}",
+                response.status_code
+            )),
+            Ok(_) => Ok(()),
+            Err(DockerError::DockerContainerWaitError { code, .. }) => {
+                Err(anyhow!("container exited with status {}", code))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn start_and_wait(&self, id: &str, log: &StageLog) -> Result<()> {
+        self.docker.start_container::<String>(id, None).await?;
+
+        let mut stdout = LineBuffer::new(log, Stream::Stdout);
+        let mut stderr = LineBuffer::new(log, Stream::Stderr);
+        let mut logs = self.docker.logs(
+            id,
+            Some(LogsOptions::<String> {
+                follow: true,
+                stdout: true,
+                stderr: true,
+                ..Default::default()
+            }),
+        );
+
+        while let Some(output) = logs.next().await {
+            match output? {
+                LogOutput::StdOut { message } | LogOutput::Console { message } => {
+                    stdout.push(&message)
+                }
+                LogOutput::StdErr { message } => stderr.push(&message),
+                LogOutput::StdIn { .. } => {}
+            }
+        }
+        stdout.flush();
+        stderr.flush();
+
+        let mut wait = self
+            .docker
+            .wait_container(id, None::<WaitContainerOptions<String>>);
+
+        while let Some(response) = wait.next().await {
+            ApiEngine::check_exit(response)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Engine for ApiEngine {
+    fn name(&self) -> &str {
+        &self.description
+    }
+
+    async fn ensure_availability(&self) -> Result<()> {
+        self.docker
+            .ping()
+            .await
+            .map(|_| ())
+            .with_context(|| format!("{} is not responding", self.description))
+    }
+
+    async fn image_exists(&self, image: &str) -> Result<bool> {
+        match self.docker.inspect_image(image).await {
+            Ok(_) => Ok(true),
+            Err(DockerError::DockerResponseServerError {
+                status_code: 404, ..
+            }) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn pull_image(&self, image: &str, log: &StageLog) -> Result<()> {
+        let mut progress = self.docker.create_image(
+            Some(CreateImageOptions {
+                from_image: image,
+                ..Default::default()
+            }),
+            None,
+            None,
+        );
+
+        while let Some(info) = progress.next().await {
+            let info = info.with_context(|| format!("Failed to pull {}", image))?;
+            ApiEngine::log_pull_progress(image, info, log)?;
+        }
+
+        Ok(())
+    }
+
+    async fn image_digest(&self, image: &str) -> Result<String> {
+        let inspect = self
+            .docker
+            .inspect_image(image)
+            .await
+            .with_context(|| format!("Could not inspect image `{}`", image))?;
+
+        Ok(pick_digest(
+            image,
+            &inspect.repo_digests.unwrap_or_default(),
+            &inspect.id.unwrap_or_default(),
+        ))
+    }
+
+    async fn build_image(&self, dockerfile: &Path, tag: &str, log: &StageLog) -> Result<String> {
+        let dockerfile = dockerfile.canonicalize()?;
+        let (context, dockerfile) =
+            ApiEngine::build_context(&dockerfile).context("Could not pack the build context")?;
+
+        let mut progress = self.docker.build_image(
+            BuildImageOptions {
+                dockerfile,
+                t: tag.to_string(),
+                rm: true,
+                ..Default::default()
+            },
+            None,
+            Some(context.into()),
+        );
+
+        while let Some(info) = progress.next().await {
+            ApiEngine::log_build_progress(info?, log)?;
+        }
+
+        Ok(tag.to_string())
+    }
+
+    async fn run_image(&self, options: &RunOptions, log: &StageLog) -> Result<()> {
+        prepare_binds(&options.binds).await?;
+
+        if !self.image_exists(&options.image).await? {
+            if options.offline {
+                return Err(anyhow!(
+                    "Image `{}` is not available locally and network access is disabled",
+                    options.image
+                ));
+            }
+
+            self.pull_image(&options.image, log).await?;
+        }
+
+        let mapping = self.user_mapping().await?;
+        let config = Config {
+            image: Some(options.image.clone()),
+            user: options.user(mapping),
+            env: Some(options.env()),
+            host_config: Some(HostConfig {
+                binds: Some(options.bind_specs(true)),
+                userns_mode: options.userns(mapping),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        debug!("container config: {config:?}");
+
+        let id = self
+            .docker
+            .create_container(None::<CreateContainerOptions<String>>, config)
+            .await?
+            .id;
+        running().push((self.docker.clone(), id.clone()));
+
+        let result = self.start_and_wait(&id, log).await;
+
+        running().retain(|(_, running)| running != &id);
+        if let Err(err) = self
+            .docker
+            .remove_container(
+                &id,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+        {
+            warn!("Could not remove container {}: {}", id, err);
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::BuildOutput;
+    use std::{fs, io::Read};
+
+    fn quiet_log() -> StageLog {
+        BuildOutput::new(true, None).unwrap().stage("test").unwrap()
+    }
+
+    fn lines(log: &StageLog) -> Vec<String> {
+        log.captured().into_iter().map(|(_, line)| line).collect()
+    }
+
+    #[test]
+    fn packs_build_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = dir.path().join("backend");
+        fs::create_dir_all(backend.join("src")).unwrap();
+        fs::write(backend.join("Dockerfile"), "FROM alpine\n").unwrap();
+        fs::write(backend.join("src/main.c"), "int main() {}\n").unwrap();
+        fs::write(dir.path().join("outside"), "linked\n").unwrap();
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::symlink(dir.path().join("outside"), backend.join("link")).unwrap();
+
+        let (context, name) = ApiEngine::build_context(&backend.join("Dockerfile")).unwrap();
+        assert_eq!(name, "Dockerfile");
+
+        let mut archive = tar::Archive::new(context.as_slice());
+        let mut files = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).unwrap();
+                (
+                    entry.path().unwrap().to_string_lossy().to_string(),
+                    contents,
+                )
+            })
+            .filter(|(_, contents)| !contents.is_empty())
+            .collect::<Vec<_>>();
+        files.sort();
+
+        let mut expected = vec![
+            ("Dockerfile".to_string(), "FROM alpine\n".to_string()),
+            ("src/main.c".to_string(), "int main() {}\n".to_string()),
+        ];
+        #[cfg(target_family = "unix")]
+        expected.insert(1, ("link".to_string(), "linked\n".to_string()));
+        assert_eq!(files, expected);
+
+        assert!(ApiEngine::build_context(Path::new("/")).is_err());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn rejects_non_utf8_dockerfile_names() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let dockerfile = dir.path().join(OsStr::from_bytes(b"Dockerfile\xff"));
+
+        let error = ApiEngine::build_context(&dockerfile).unwrap_err();
+        assert!(error.to_string().contains("is not a valid Dockerfile name"));
+    }
+
+    #[test]
+    fn logs_pull_progress() {
+        let log = quiet_log();
+        let progress = |id: Option<&str>, status: &str, progress: Option<&str>| CreateImageInfo {
+            id: id.map(Into::into),
+            status: Some(status.into()),
+            progress: progress.map(Into::into),
+            ..Default::default()
+        };
+
+        for info in [
+            progress(None, "Pulling from steamdeckhomebrew/builder", None),
+            progress(Some("a1b2"), "Downloading", Some("[=>   ] 1MB/10MB")),
+            progress(Some("a1b2"), "Pull complete", None),
+        ] {
+            ApiEngine::log_pull_progress("builder", info, &log).unwrap();
+        }
+        assert_eq!(
+            lines(&log),
+            [
+                "Pulling from steamdeckhomebrew/builder",
+                "a1b2: Pull complete"
+            ]
+        );
+
+        let error = ApiEngine::log_pull_progress(
+            "builder",
+            CreateImageInfo {
+                error: Some("manifest unknown".into()),
+                ..Default::default()
+            },
+            &log,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Failed to pull builder: manifest unknown"
+        );
+    }
+
+    #[test]
+    fn logs_build_progress() {
+        let log = quiet_log();
+
+        for info in [
+            BuildInfo {
+                stream: Some("Step 1/2 : FROM alpine\n\n ---> 1234\n".into()),
+                ..Default::default()
+            },
+            BuildInfo {
+                status: Some("Downloading".into()),
+                progress: Some("[=>  ]".into()),
+                ..Default::default()
+            },
+            BuildInfo {
+                status: Some("Download complete".into()),
+                ..Default::default()
+            },
+        ] {
+            ApiEngine::log_build_progress(info, &log).unwrap();
+        }
+        assert_eq!(
+            lines(&log),
+            ["Step 1/2 : FROM alpine", " ---> 1234", "Download complete"]
+        );
+
+        let error = ApiEngine::log_build_progress(
+            BuildInfo {
+                error: Some("make: not found".into()),
+                ..Default::default()
+            },
+            &log,
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Image build failed: make: not found");
+    }
+
+    #[test]
+    fn checks_container_exit() {
+        let exited = |status_code| {
+            Ok(ContainerWaitResponse {
+                status_code,
+                error: None,
+            })
+        };
+
+        assert!(ApiEngine::check_exit(exited(0)).is_ok());
+        assert_eq!(
+            ApiEngine::check_exit(exited(2)).unwrap_err().to_string(),
+            "container exited with status 2"
+        );
+        assert_eq!(
+            ApiEngine::check_exit(Err(DockerError::DockerContainerWaitError {
+                error: "".into(),
+                code: 137
+            }))
+            .unwrap_err()
+            .to_string(),
+            "container exited with status 137"
+        );
+        assert!(
+            ApiEngine::check_exit(Err(DockerError::DockerResponseServerError {
+                status_code: 500,
+                message: "daemon error".into()
+            }))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn splits_output_into_lines() {
+        let log = quiet_log();
+        let mut buffer = LineBuffer::new(&log, Stream::Stdout);
+
+        buffer.push(b"first li");
+        buffer.push(b"ne\r\nsecond\nthi");
+        assert_eq!(lines(&log), ["first line", "second"]);
+
+        buffer.flush();
+        assert_eq!(lines(&log), ["first line", "second", "thi"]);
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use log::debug;
+use std::{path::Path, process::Stdio};
+use tokio::{process::Command, sync::OnceCell};
+use which::which;
+
+use super::{pick_digest, prepare_binds, run_command, Engine, RunOptions, UserMapping};
+use crate::{cli::ContainerEngine, output::StageLog};
+
+/// Drives docker, podman or nerdctl by running their command line clients
+pub struct CliEngine {
+    bin_name: String,
+    description: String,
+    kind: ContainerEngine,
+    user_mapping: OnceCell<UserMapping>,
+}
+
+impl CliEngine {
+    pub fn new(engine: &ContainerEngine) -> Self {
+        let names = engine.cli_names();
+        let bin_name = names
+            .iter()
+            .find(|name| which(name).is_ok())
+            .or(names.first())
+            .copied()
+            .unwrap_or(engine.bin_name());
+
+        Self {
+            bin_name: bin_name.to_string(),
+            description: format!("{} (command line)", bin_name),
+            kind: engine.clone(),
+            user_mapping: OnceCell::new(),
+        }
+    }
+
+    fn command(&self) -> Command {
+        Command::new(&self.bin_name)
+    }
+
+    async fn is_rootless(&self) -> Result<bool> {
+        let format = match self.kind {
+            ContainerEngine::Podman => "{{.Host.Security.Rootless}}",
+            _ => "{{json .SecurityOptions}}",
+        };
+
+        let output = self
+            .command()
+            .arg("info")
+            .arg("--format")
+            .arg(format)
+            .stderr(Stdio::inherit())
+            .output()
+            .await
+            .context(format!("Failed to run `{} info`", self.bin_name))?;
+
+        if !output.status.success() {
+            return Err(anyhow!("`{} info` failed", self.bin_name));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(match self.kind {
+            ContainerEngine::Podman => stdout.trim() == "true",
+            _ => stdout.contains("rootless"),
+        })
+    }
+
+    async fn user_mapping(&self) -> Result<UserMapping> {
+        self.user_mapping
+            .get_or_try_init(|| async {
+                let rootless = self.is_rootless().await?;
+                let mapping = UserMapping::from_rootless(&self.kind, rootless);
+                debug!("{} user mapping: {:?}", self.bin_name, mapping);
+                Ok(mapping)
+            })
+            .await
+            .copied()
+    }
+}
+
+#[async_trait]
+impl Engine for CliEngine {
+    fn name(&self) -> &str {
+        &self.description
+    }
+
+    async fn ensure_availability(&self) -> Result<()> {
+        which(&self.bin_name)
+            .context(format!("`{}` program not found. Make sure it is installed and in your $PATH. For more information visit https://docs.docker.com/desktop/troubleshoot/overview/", self.bin_name))?;
+
+        let exit_status = self
+            .command()
+            .arg("ps")
+            .status()
+            .await
+            .context(format!("Error while checking for {0} availability. Please run `{0} ps` in your terminal and fix any errors that show up.", self.bin_name))?;
+
+        if !exit_status.success() {
+            Err(anyhow!("exit status {}: {1} is installed but doesn't seem to be available! Is the daemon running? For more information visit https://docs.{1}.com/desktop/troubleshoot/overview/", exit_status.code().unwrap_or(-1), self.bin_name))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn image_exists(&self, image: &str) -> Result<bool> {
+        let status = self
+            .command()
+            .arg("image")
+            .arg("inspect")
+            .arg(image)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .context(format!("Failed to run `{} image inspect`", self.bin_name))?;
+
+        Ok(status.success())
+    }
+
+    async fn pull_image(&self, image: &str, log: &StageLog) -> Result<()> {
+        run_command(self.command().arg("pull").arg(image), log).await
+    }
+
+    async fn image_digest(&self, image: &str) -> Result<String> {
+        let output = self
+            .command()
+            .arg("image")
+            .arg("inspect")
+            .arg("--format")
+            .arg("{{json .RepoDigests}} {{.Id}}")
+            .arg(image)
+            .stderr(Stdio::inherit())
+            .output()
+            .await
+            .context(format!("Failed to run `{} image inspect`", self.bin_name))?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Could not inspect image `{}`", image));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let (repo_digests, id) = stdout
+            .trim()
+            .rsplit_once(' ')
+            .ok_or_else(|| anyhow!("Unexpected output from `{} image inspect`", self.bin_name))?;
+
+        let repo_digests: Vec<String> = serde_json::from_str(repo_digests).unwrap_or_default();
+        Ok(pick_digest(image, &repo_digests, id))
+    }
+
+    // docker build -f $PWD/backend/Dockerfile -t "$docker_name" .
+    async fn build_image(&self, dockerfile: &Path, tag: &str, log: &StageLog) -> Result<String> {
+        let dockerfile = dockerfile.canonicalize()?;
+
+        let mut cmd = self.command();
+        let full_command = cmd
+            .arg("build")
+            .arg("-f")
+            .arg(&dockerfile)
+            .arg("-t")
+            .arg(tag)
+            .arg(dockerfile.parent().unwrap());
+
+        run_command(full_command, log).await?;
+
+        Ok(tag.to_string())
+    }
+
+    // docker run --rm -i -v $PWD/backend:/backend -v /tmp/output/$plugin/backend/out:/backend/out --entrypoint /backend/entrypoint.sh "$docker_name"
+    async fn run_image(&self, options: &RunOptions, log: &StageLog) -> Result<()> {
+        let mut cmd = self.command();
+        let mut command_with_default_args = cmd.arg("run").arg("--rm");
+
+        if options.offline {
+            command_with_default_args = command_with_default_args.arg("--pull=never");
+        }
+
+        let mapping = self.user_mapping().await?;
+
+        if let Some(userns) = options.userns(mapping) {
+            command_with_default_args =
+                command_with_default_args.arg(format!("--userns={}", userns));
+        }
+
+        if let Some(user) = options.user(mapping) {
+            command_with_default_args = command_with_default_args.arg("--user").arg(user);
+        }
+
+        for env in options.env() {
+            command_with_default_args = command_with_default_args.arg("-e").arg(env);
+        }
+
+        prepare_binds(&options.binds).await?;
+
+        // nerdctl does not understand the SELinux relabeling option
+        let relabel = !matches!(self.kind, ContainerEngine::Nerdctl);
+        for bind in options.bind_specs(relabel) {
+            command_with_default_args = command_with_default_args.arg("-v").arg(bind);
+        }
+
+        let full_command = command_with_default_args.arg(&options.image);
+        debug!("full_command: {full_command:?}");
+        run_command(full_command, log).await?;
+
+        Ok(())
+    }
+}
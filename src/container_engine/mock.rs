@@ -0,0 +1,178 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use super::{Engine, RunOptions};
+use crate::output::{StageLog, Stream};
+
+/// A call made to [`MockEngine`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Call {
+    ImageExists(String),
+    PullImage(String),
+    ImageDigest(String),
+    BuildImage(PathBuf, String),
+    RunImage(RunOptions),
+}
+
+/// File a container "produces" in the directory bound to `target`
+struct Output {
+    target: String,
+    file: PathBuf,
+    contents: Vec<u8>,
+}
+
+/// In-process engine that records every call instead of running containers.
+/// Runs "succeed" by writing the configured outputs into their bind mounts.
+#[derive(Default)]
+pub struct MockEngine {
+    images: Mutex<HashSet<String>>,
+    digests: HashMap<String, String>,
+    outputs: Vec<Output>,
+    failing_image: Option<String>,
+    delay: Duration,
+    running: AtomicUsize,
+    max_running: AtomicUsize,
+    calls: Mutex<Vec<Call>>,
+}
+
+impl MockEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pretends `image` is already present locally.
+    pub fn with_image(self, image: &str) -> Self {
+        self.images.lock().unwrap().insert(image.to_string());
+        self
+    }
+
+    /// Reports `digest` for `image` instead of a made-up one.
+    pub fn with_digest(mut self, image: &str, digest: &str) -> Self {
+        self.digests.insert(image.to_string(), digest.to_string());
+        self
+    }
+
+    /// Writes `contents` to `file` inside the bind mounted at `target` whenever
+    /// a container with that mount runs.
+    pub fn with_output(mut self, target: &str, file: &str, contents: &[u8]) -> Self {
+        self.outputs.push(Output {
+            target: target.to_string(),
+            file: file.into(),
+            contents: contents.to_vec(),
+        });
+        self
+    }
+
+    /// Makes running `image` fail as if the container exited with an error.
+    pub fn failing(mut self, image: &str) -> Self {
+        self.failing_image = Some(image.to_string());
+        self
+    }
+
+    /// Makes every container take `delay` to run.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Most containers that were running at the same time
+    pub fn max_running(&self) -> usize {
+        self.max_running.load(Ordering::SeqCst)
+    }
+
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    pub fn runs(&self) -> Vec<RunOptions> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::RunImage(options) => Some(options),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn record(&self, call: Call) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+#[async_trait]
+impl Engine for MockEngine {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    async fn ensure_availability(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn image_exists(&self, image: &str) -> Result<bool> {
+        self.record(Call::ImageExists(image.to_string()));
+        Ok(self.images.lock().unwrap().contains(image))
+    }
+
+    async fn pull_image(&self, image: &str, _log: &StageLog) -> Result<()> {
+        self.record(Call::PullImage(image.to_string()));
+        self.images.lock().unwrap().insert(image.to_string());
+        Ok(())
+    }
+
+    async fn image_digest(&self, image: &str) -> Result<String> {
+        self.record(Call::ImageDigest(image.to_string()));
+        Ok(self
+            .digests
+            .get(image)
+            .cloned()
+            .unwrap_or_else(|| format!("{}@sha256:{}", image, "0".repeat(64))))
+    }
+
+    async fn build_image(&self, dockerfile: &Path, tag: &str, _log: &StageLog) -> Result<String> {
+        self.record(Call::BuildImage(dockerfile.to_path_buf(), tag.to_string()));
+        self.images.lock().unwrap().insert(tag.to_string());
+        Ok(tag.to_string())
+    }
+
+    async fn run_image(&self, options: &RunOptions, log: &StageLog) -> Result<()> {
+        self.record(Call::RunImage(options.clone()));
+
+        log.line(Stream::Stdout, &format!("running {}", options.image));
+
+        let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_running.fetch_max(running, Ordering::SeqCst);
+        tokio::time::sleep(self.delay).await;
+        self.running.fetch_sub(1, Ordering::SeqCst);
+
+        if self.failing_image.as_ref() == Some(&options.image) {
+            log.line(Stream::Stderr, "error: build failed");
+            return Err(anyhow!("container exited with status 1"));
+        }
+
+        for output in &self.outputs {
+            let Some((source, _)) = options
+                .binds
+                .iter()
+                .find(|(_, target)| target == &output.target)
+            else {
+                continue;
+            };
+
+            let destination = Path::new(source).join(&output.file);
+            std::fs::create_dir_all(destination.parent().unwrap())?;
+            std::fs::write(destination, &output.contents)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,382 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use log::{debug, info};
+use std::{future::Future, path::Path, process::Stdio, sync::Arc};
+use tokio::{
+    fs::create_dir_all,
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Command,
+};
+use uzers::{get_effective_gid, get_effective_uid};
+
+use crate::{
+    cli::{ContainerEngine, EngineTransport},
+    output::{StageLog, Stream},
+};
+
+mod api;
+mod cli;
+#[cfg(test)]
+mod mock;
+mod native;
+
+pub use api::ApiEngine;
+pub use cli::CliEngine;
+#[cfg(test)]
+pub use mock::{Call, MockEngine};
+pub use native::{native_package_manager, NativeEngine};
+
+/// How an engine maps users inside containers onto the host
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserMapping {
+    /// The daemon runs as root, container UIDs are host UIDs
+    Rootful,
+    /// Rootless podman, which can map the invoking user to the same UID in
+    /// the container with `--userns=keep-id`
+    KeepId,
+    /// Rootless docker or nerdctl, where root in the container is the
+    /// invoking user and every other UID is a subordinate one
+    RootIsHostUser,
+}
+
+impl UserMapping {
+    pub fn from_rootless(engine: &ContainerEngine, rootless: bool) -> Self {
+        match (rootless, engine) {
+            (false, _) => Self::Rootful,
+            (true, ContainerEngine::Podman) => Self::KeepId,
+            (true, _) => Self::RootIsHostUser,
+        }
+    }
+}
+
+/// Everything needed to run a build container to completion
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunOptions {
+    pub image: String,
+    /// (host path, container path) pairs
+    pub binds: Vec<(String, String)>,
+    pub run_as_root: bool,
+    pub run_with_dev: bool,
+    pub offline: bool,
+}
+
+impl RunOptions {
+    /// User to run as, or None to keep the image's default user. Files the
+    /// container writes to binds end up owned by the invoking user unless
+    /// running as root on a rootful engine.
+    pub fn user(&self, mapping: UserMapping) -> Option<String> {
+        match (mapping, self.run_as_root) {
+            (UserMapping::RootIsHostUser, _) => Some("0:0".into()),
+            (_, true) => None,
+            (_, false) => Some(format!("{}:{}", get_effective_uid(), get_effective_gid())),
+        }
+    }
+
+    /// User namespace mode to run in, or None for the engine's default.
+    pub fn userns(&self, mapping: UserMapping) -> Option<String> {
+        match (mapping, self.run_as_root) {
+            (UserMapping::KeepId, false) => Some("keep-id".into()),
+            _ => None,
+        }
+    }
+
+    pub fn env(&self) -> Vec<String> {
+        match self.run_with_dev {
+            true => vec!["RELEASE_TYPE=development".into()],
+            false => vec!["RELEASE_TYPE=production".into()],
+        }
+    }
+
+    /// `source:target` specs, with `relabel` adding `:z` which avoids issues with selinux
+    /// https://docs.docker.com/storage/bind-mounts/#configure-the-selinux-label
+    pub fn bind_specs(&self, relabel: bool) -> Vec<String> {
+        self.binds
+            .iter()
+            .map(|(source, target)| match relabel {
+                true => format!("{}:{}:z", source, target),
+                false => format!("{}:{}", source, target),
+            })
+            .collect()
+    }
+}
+
+/// Operations the builder needs from a container engine
+#[async_trait]
+pub trait Engine: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn ensure_availability(&self) -> Result<()>;
+
+    async fn image_exists(&self, image: &str) -> Result<bool>;
+
+    async fn pull_image(&self, image: &str, log: &StageLog) -> Result<()>;
+
+    /// Content digest identifying exactly which image `image` refers to locally,
+    /// e.g. `ghcr.io/steamdeckhomebrew/builder@sha256:...`, or the image ID if it
+    /// was never pushed to a registry.
+    async fn image_digest(&self, image: &str) -> Result<String>;
+
+    /// Builds `dockerfile` using its directory as context and returns the tag.
+    async fn build_image(&self, dockerfile: &Path, tag: &str, log: &StageLog) -> Result<String>;
+
+    async fn run_image(&self, options: &RunOptions, log: &StageLog) -> Result<()>;
+}
+
+/// Picks the repository digest matching `image`, falling back to the image ID.
+pub fn pick_digest(image: &str, repo_digests: &[String], id: &str) -> String {
+    let repository = image.split('@').next().unwrap_or(image);
+    let repository = match repository.rsplit_once(':') {
+        Some((name, tag)) if !tag.contains('/') => name,
+        _ => repository,
+    };
+
+    repo_digests
+        .iter()
+        .find(|digest| digest.split('@').next() == Some(repository))
+        .or(repo_digests.first())
+        .cloned()
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// Pre-creates bind-mounted directories as the current user, otherwise they are
+/// created by the container daemon, which may be a different user. Containers
+/// run as a user that maps back to the current one, so they can write to them.
+pub async fn prepare_binds(binds: &[(String, String)]) -> Result<()> {
+    for (source, _) in binds {
+        create_dir_all(source).await?;
+    }
+
+    Ok(())
+}
+
+async fn forward_lines(
+    reader: impl AsyncRead + Unpin,
+    log: &StageLog,
+    stream: Stream,
+) -> std::io::Result<()> {
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        log.line(stream, &line);
+    }
+
+    Ok(())
+}
+
+/// Runs `cmd` to completion, passing its stdout and stderr to `log` line by line.
+pub async fn run_command(cmd: &mut Command, log: &StageLog) -> Result<()> {
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to run {:?}", cmd.as_std().get_program()))?;
+
+    let stdout = child
+        .stdout
+        .take()
+        .expect("child did not have a handle to stdout");
+    let stderr = child
+        .stderr
+        .take()
+        .expect("child did not have a handle to stderr");
+
+    tokio::try_join!(
+        forward_lines(stdout, log, Stream::Stdout),
+        forward_lines(stderr, log, Stream::Stderr),
+    )?;
+
+    let status = child.wait().await?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(anyhow!("child status was: {}", status)),
+    }
+}
+
+async fn connect_to(
+    engine: &ContainerEngine,
+    transport: &EngineTransport,
+) -> Result<Arc<dyn Engine>> {
+    let connected: Arc<dyn Engine> = match (engine, transport) {
+        (ContainerEngine::Native, _) => Arc::new(NativeEngine),
+        (ContainerEngine::Auto, _) => unreachable!("auto is resolved by `connect`"),
+        (_, EngineTransport::Cli) => Arc::new(CliEngine::new(engine)),
+        (_, EngineTransport::Socket) => Arc::new(ApiEngine::connect(engine)?),
+        (_, EngineTransport::Auto) => match ApiEngine::connect(engine) {
+            Ok(api) => match api.ensure_availability().await {
+                Ok(()) => Arc::new(api),
+                Err(err) => {
+                    debug!("{:#}, falling back to the {} CLI", err, engine.bin_name());
+                    Arc::new(CliEngine::new(engine))
+                }
+            },
+            Err(err) => {
+                debug!("{:#}, falling back to the {} CLI", err, engine.bin_name());
+                Arc::new(CliEngine::new(engine))
+            }
+        },
+    };
+
+    connected.ensure_availability().await?;
+
+    Ok(connected)
+}
+
+/// Tries each of `ContainerEngine::DETECTABLE` in turn and picks the first
+/// one that works.
+async fn detect(transport: &EngineTransport) -> Result<(ContainerEngine, Arc<dyn Engine>)> {
+    detect_with(|candidate| async move { connect_to(&candidate, transport).await }).await
+}
+
+/// [`detect`], with `probe` connecting to each candidate
+async fn detect_with<F, Fut>(probe: F) -> Result<(ContainerEngine, Arc<dyn Engine>)>
+where
+    F: Fn(ContainerEngine) -> Fut,
+    Fut: Future<Output = Result<Arc<dyn Engine>>>,
+{
+    let mut skipped = vec![];
+
+    for candidate in ContainerEngine::DETECTABLE {
+        match probe(candidate.clone()).await {
+            Ok(engine) => return Ok((candidate, engine)),
+            Err(err) => {
+                let reason = err.to_string();
+                info!("Skipping {}: {}", candidate.bin_name(), reason);
+                skipped.push(format!("{}: {}", candidate.bin_name(), reason));
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "No working container engine found:\n  - {}\nInstall and start docker, podman or nerdctl, or pass `--engine native` to build without containers.",
+        skipped.join("\n  - ")
+    ))
+}
+
+/// Connects to `engine` using the requested transport and checks that it works.
+/// Returns the engine that was picked, which only differs from `engine` for `auto`.
+pub async fn connect(
+    engine: &ContainerEngine,
+    transport: &EngineTransport,
+) -> Result<(ContainerEngine, Arc<dyn Engine>)> {
+    let (engine, connected) = match engine {
+        ContainerEngine::Auto => detect(transport).await?,
+        _ => (engine.clone(), connect_to(engine, transport).await?),
+    };
+
+    info!("Using {}", connected.name());
+
+    Ok((engine, connected))
+}
+
+/// Stops and removes containers that are still running, e.g. after Ctrl-C.
+pub async fn shutdown() {
+    api::remove_running_containers().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(run_as_root: bool) -> RunOptions {
+        RunOptions {
+            image: "builder".into(),
+            binds: vec![("/tmp/out".into(), "/out".into())],
+            run_as_root,
+            run_with_dev: false,
+            offline: false,
+        }
+    }
+
+    #[test]
+    fn maps_users_per_engine_mode() {
+        let invoking = Some(format!("{}:{}", get_effective_uid(), get_effective_gid()));
+        let root = Some("0:0".to_string());
+        let keep_id = Some("keep-id".to_string());
+
+        for (mapping, run_as_root, user, userns) in [
+            (UserMapping::Rootful, false, &invoking, &None),
+            (UserMapping::Rootful, true, &None, &None),
+            (UserMapping::KeepId, false, &invoking, &keep_id),
+            (UserMapping::KeepId, true, &None, &None),
+            (UserMapping::RootIsHostUser, false, &root, &None),
+            (UserMapping::RootIsHostUser, true, &root, &None),
+        ] {
+            let options = options(run_as_root);
+            assert_eq!(
+                &options.user(mapping),
+                user,
+                "{mapping:?} as root: {run_as_root}"
+            );
+            assert_eq!(
+                &options.userns(mapping),
+                userns,
+                "{mapping:?} as root: {run_as_root}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn detects_engines_in_order() {
+        let probed = std::sync::Mutex::new(vec![]);
+        let probe = |working: &'static [ContainerEngine]| {
+            let probed = &probed;
+            move |candidate: ContainerEngine| async move {
+                probed.lock().unwrap().push(candidate.clone());
+                match working.contains(&candidate) {
+                    true => Ok(Arc::new(MockEngine::new()) as Arc<dyn Engine>),
+                    false => Err(anyhow!("not running")),
+                }
+            }
+        };
+
+        let (engine, _) = detect_with(probe(&[ContainerEngine::Podman, ContainerEngine::Nerdctl]))
+            .await
+            .unwrap();
+        assert_eq!(engine, ContainerEngine::Podman);
+        assert_eq!(
+            probed.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            [ContainerEngine::Docker, ContainerEngine::Podman]
+        );
+
+        let (engine, _) = detect_with(probe(&[ContainerEngine::Docker, ContainerEngine::Podman]))
+            .await
+            .unwrap();
+        assert_eq!(engine, ContainerEngine::Docker);
+        assert_eq!(
+            probed.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            [ContainerEngine::Docker]
+        );
+
+        let error = detect_with(probe(&[])).await.err().unwrap().to_string();
+        assert_eq!(
+            probed.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            ContainerEngine::DETECTABLE
+        );
+        assert!(
+            error.starts_with(
+                "No working container engine found:\n  - docker: not running\n  - podman: not running\n  - nerdctl: not running\n"
+            ),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn detects_user_mapping() {
+        assert_eq!(
+            UserMapping::from_rootless(&ContainerEngine::Podman, true),
+            UserMapping::KeepId
+        );
+        assert_eq!(
+            UserMapping::from_rootless(&ContainerEngine::Docker, true),
+            UserMapping::RootIsHostUser
+        );
+        assert_eq!(
+            UserMapping::from_rootless(&ContainerEngine::Nerdctl, true),
+            UserMapping::RootIsHostUser
+        );
+        assert_eq!(
+            UserMapping::from_rootless(&ContainerEngine::Podman, false),
+            UserMapping::Rootful
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+use which::which_in;
+
+use super::{Engine, RunOptions};
+use crate::output::StageLog;
+
+/// JavaScript package manager used by the native engine, in order of preference.
+/// Looks in `search_path` if given, `$PATH` otherwise.
+pub fn native_package_manager(search_path: Option<&OsStr>) -> Result<PathBuf> {
+    let search_path = search_path
+        .map(OsStr::to_os_string)
+        .or_else(|| std::env::var_os("PATH"));
+
+    ["pnpm", "npm"]
+        .into_iter()
+        .find_map(|bin| which_in(bin, search_path.as_ref(), ".").ok())
+        .ok_or_else(|| anyhow!("Neither `pnpm` nor `npm` found in your $PATH. The native engine needs one of them to build the frontend."))
+}
+
+/// Stand-in engine for building on the host, which has no container support
+pub struct NativeEngine;
+
+impl NativeEngine {
+    fn unsupported<T>(&self) -> Result<T> {
+        Err(anyhow!(
+            "The native engine cannot run containers. Use a container engine like docker or podman instead."
+        ))
+    }
+}
+
+#[async_trait]
+impl Engine for NativeEngine {
+    fn name(&self) -> &str {
+        "native"
+    }
+
+    async fn ensure_availability(&self) -> Result<()> {
+        native_package_manager(None).map(|_| ())
+    }
+
+    async fn image_exists(&self, _image: &str) -> Result<bool> {
+        self.unsupported()
+    }
+
+    async fn pull_image(&self, _image: &str, _log: &StageLog) -> Result<()> {
+        self.unsupported()
+    }
+
+    async fn image_digest(&self, _image: &str) -> Result<String> {
+        self.unsupported()
+    }
+
+    async fn build_image(&self, _dockerfile: &Path, _tag: &str, _log: &StageLog) -> Result<String> {
+        self.unsupported()
+    }
+
+    async fn run_image(&self, _options: &RunOptions, _log: &StageLog) -> Result<()> {
+        self.unsupported()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn install(dir: &Path, bin: &str) {
+        let path = dir.join(bin);
+        fs::write(&path, "#!/bin/sh\n").unwrap();
+        #[cfg(target_family = "unix")]
+        fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn prefers_pnpm_over_npm() {
+        let npm_only = tempfile::tempdir().unwrap();
+        install(npm_only.path(), "npm");
+        let both = tempfile::tempdir().unwrap();
+        install(both.path(), "npm");
+        install(both.path(), "pnpm");
+        let empty = tempfile::tempdir().unwrap();
+
+        let find = |dir: &Path| native_package_manager(Some(dir.as_os_str()));
+
+        assert_eq!(find(npm_only.path()).unwrap(), npm_only.path().join("npm"));
+        assert_eq!(find(both.path()).unwrap(), both.path().join("pnpm"));
+        assert!(find(empty.path())
+            .unwrap_err()
+            .to_string()
+            .starts_with("Neither `pnpm` nor `npm` found"));
+    }
+}
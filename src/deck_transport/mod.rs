@@ -0,0 +1,320 @@
+use anyhow::{anyhow, Context, Result};
+use dirs::home_dir;
+use itertools::Itertools;
+use log::info;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::cli::{plugin::deploy::DeckFile, DeployTransport};
+
+mod native;
+mod openssh;
+
+pub use native::NativeTransport;
+pub use openssh::OpenSshTransport;
+
+/// User the plugin loader runs as on a Deck
+pub const DECK_USER: &str = "deck";
+
+/// A way of running commands on a Deck and copying files to it
+pub trait Transport {
+    fn name(&self) -> &str;
+
+    fn connection(&self) -> &DeckConnection;
+
+    /// Runs `command` in the remote user's shell, writing `input` to its stdin,
+    /// and fails if it exits with an error.
+    fn run(&self, command: &str, input: Option<&str>) -> Result<()>;
+
+    fn create_dir_all(&self, path: &str) -> Result<()>;
+
+    /// Replaces the remote directory `remote` with the contents of `local`,
+    /// making everything in it executable the way the plugin loader expects.
+    fn sync_dir(&self, local: &Path, remote: &str) -> Result<()>;
+
+    /// Runs `command` as root. The password goes to sudo over stdin, so it
+    /// never shows up in the remote command line. With `no_sudo`, the Deck
+    /// has to allow sudo without a password instead.
+    fn run_as_root(&self, command: &str, no_sudo: bool) -> Result<()> {
+        match no_sudo {
+            true => self.run(&format!("sudo -n {}", command), None),
+            false => self.run(
+                &format!("sudo -S -p '' {}", command),
+                Some(&format!("{}\n", self.connection().password)),
+            ),
+        }
+    }
+}
+
+/// Quotes `value` for a POSIX shell.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Where and how to reach a Deck, shared by every step of a deploy
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeckConnection {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub key: Option<PathBuf>,
+    pub password: String,
+}
+
+/// Parses a port given as text in deck.json or on the command line.
+pub fn parse_port(port: &str) -> Result<u16> {
+    port.trim()
+        .parse()
+        .with_context(|| anyhow!("Invalid deck port `{}`", port))
+}
+
+impl DeckConnection {
+    pub fn from_deckfile(deck: &DeckFile) -> Result<Self> {
+        Ok(Self {
+            host: deck.deckip.clone(),
+            port: parse_port(&deck.deckport)?,
+            user: deck
+                .deckuser
+                .clone()
+                .unwrap_or_else(|| DECK_USER.to_string()),
+            key: Self::key_path(&deck.deckkey)?,
+            password: deck.deckpass.clone(),
+        })
+    }
+
+    /// Key file from deck.json's `deckkey` or `--deck-key`, given either as ssh's
+    /// `-i <path>` option or as just the path. None if it is empty.
+    pub fn key_path(deckkey: &str) -> Result<Option<PathBuf>> {
+        let home = home_dir().unwrap_or_default();
+        let home = home.to_string_lossy();
+
+        let deckkey = deckkey.trim();
+        if deckkey.is_empty() {
+            return Ok(None);
+        }
+
+        let path = match deckkey.strip_prefix("-i") {
+            Some(path) if !path.trim().is_empty() => path.trim(),
+            _ if deckkey.starts_with('-') => {
+                return Err(anyhow!(
+                    "Invalid deck key `{}`, expected the path to a private key or `-i <path>`",
+                    deckkey
+                ))
+            }
+            _ => deckkey,
+        };
+
+        let path = path.replace("${env:HOME}", &home).replace("$HOME", &home);
+
+        Ok(Some(match path.strip_prefix("~/") {
+            Some(path) => Path::new(home.as_ref()).join(path),
+            None => PathBuf::from(path),
+        }))
+    }
+
+    /// `user@host`, as ssh and rsync expect it
+    pub fn destination(&self) -> String {
+        format!("{}@{}", self.user, self.host)
+    }
+
+    /// Options that make `ssh` use this connection's port and key
+    pub fn ssh_options(&self) -> Vec<String> {
+        let mut options = vec!["-p".to_string(), self.port.to_string()];
+        if let Some(key) = &self.key {
+            options.push("-i".to_string());
+            options.push(key.to_string_lossy().to_string());
+        }
+        options
+    }
+
+    /// Remote shell for `rsync --rsh`, connecting the same way as [`Self::ssh_options`]
+    pub fn rsync_shell(&self) -> String {
+        std::iter::once("ssh".to_string())
+            .chain(self.ssh_options().iter().map(|option| shell_quote(option)))
+            .join(" ")
+    }
+}
+
+impl fmt::Display for DeckConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.destination(), self.port)
+    }
+}
+
+/// Opens a connection to the Deck.
+pub fn connect(
+    transport: &DeployTransport,
+    connection: &DeckConnection,
+) -> Result<Box<dyn Transport>> {
+    let connected: Box<dyn Transport> = match transport {
+        DeployTransport::Native => Box::new(NativeTransport::connect(connection)?),
+        DeployTransport::Openssh => Box::new(OpenSshTransport::new(connection)),
+    };
+
+    info!("Deploying over {}", connected.name());
+
+    Ok(connected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deck(key: &str, port: &str) -> DeckFile {
+        DeckFile {
+            deckip: "steamdeck".to_string(),
+            deckport: port.to_string(),
+            deckpass: "".to_string(),
+            deckkey: key.to_string(),
+            deckdir: "/home/deck".to_string(),
+            deckuser: None,
+        }
+    }
+
+    fn connect(key: &str, port: &str) -> Result<DeckConnection> {
+        DeckConnection::from_deckfile(&deck(key, port))
+    }
+
+    #[test]
+    fn reads_connection_from_deckfile() {
+        let home = home_dir().unwrap();
+        let key = |deckkey| connect(deckkey, "22").unwrap().key;
+
+        assert_eq!(key("-i $HOME/.ssh/id_rsa"), Some(home.join(".ssh/id_rsa")));
+        assert_eq!(
+            key("-i ${env:HOME}/.ssh/deck"),
+            Some(home.join(".ssh/deck"))
+        );
+        assert_eq!(key("-i ~/keys/deck"), Some(home.join("keys/deck")));
+        assert_eq!(key("-i  /keys/deck "), Some(PathBuf::from("/keys/deck")));
+        assert_eq!(key("-i/keys/deck"), Some(PathBuf::from("/keys/deck")));
+        assert_eq!(key(""), None);
+        assert_eq!(key("  "), None);
+
+        assert_eq!(connect("", " 2222 ").unwrap().port, 2222);
+        assert!(connect("", "ssh").is_err());
+    }
+
+    #[test]
+    fn reads_bare_key_paths() {
+        let home = home_dir().unwrap();
+        let key = |deckkey| DeckConnection::key_path(deckkey).unwrap();
+
+        assert_eq!(key("~/.ssh/id_ed25519"), Some(home.join(".ssh/id_ed25519")));
+        assert_eq!(key("$HOME/keys/deck"), Some(home.join("keys/deck")));
+        assert_eq!(key("/keys/my deck"), Some(PathBuf::from("/keys/my deck")));
+        assert_eq!(key("id_rsa"), Some(PathBuf::from("id_rsa")));
+    }
+
+    #[test]
+    fn rejects_unknown_key_options() {
+        for deckkey in [
+            "-o IdentityFile=/keys/deck",
+            "-i",
+            "-i ",
+            "--key /keys/deck",
+        ] {
+            let error = DeckConnection::key_path(deckkey).unwrap_err().to_string();
+            assert!(error.starts_with("Invalid deck key"), "{deckkey}: {error}");
+        }
+        assert!(connect("-o IdentityFile=/keys/deck", "22").is_err());
+    }
+
+    #[test]
+    fn connects_rsync_like_ssh() {
+        let connection = connect("-i /keys/my deck", "2222").unwrap();
+
+        assert_eq!(connection.destination(), "deck@steamdeck");
+        assert_eq!(
+            connection.ssh_options(),
+            vec!["-p", "2222", "-i", "/keys/my deck"]
+        );
+        assert_eq!(
+            connection.rsync_shell(),
+            "ssh '-p' '2222' '-i' '/keys/my deck'"
+        );
+        assert_eq!(connect("", "22").unwrap().rsync_shell(), "ssh '-p' '22'");
+    }
+
+    /// Records commands instead of running them, failing the ones containing `fail`
+    struct FakeTransport {
+        connection: DeckConnection,
+        runs: std::cell::RefCell<Vec<(String, Option<String>)>>,
+    }
+
+    impl Transport for FakeTransport {
+        fn name(&self) -> &str {
+            "fake"
+        }
+
+        fn connection(&self) -> &DeckConnection {
+            &self.connection
+        }
+
+        fn run(&self, command: &str, input: Option<&str>) -> Result<()> {
+            self.runs
+                .borrow_mut()
+                .push((command.to_string(), input.map(Into::into)));
+            match command.contains("fail") {
+                true => Err(anyhow!("Remote command exited with status 1")),
+                false => Ok(()),
+            }
+        }
+
+        fn create_dir_all(&self, _path: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn sync_dir(&self, _local: &Path, _remote: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn runs_as_root_with_password_on_stdin() {
+        let remote = FakeTransport {
+            connection: DeckConnection {
+                password: "it's secret".into(),
+                ..connect("", "22").unwrap()
+            },
+            runs: Default::default(),
+        };
+
+        remote
+            .run_as_root("systemctl restart plugin_loader.service", false)
+            .unwrap();
+        remote
+            .run_as_root("chmod -R ug+rw '/home/deck/homebrew/'", true)
+            .unwrap();
+        assert_eq!(
+            remote.run_as_root("fail", false).unwrap_err().to_string(),
+            "Remote command exited with status 1"
+        );
+
+        assert_eq!(
+            remote.runs.into_inner(),
+            [
+                (
+                    "sudo -S -p '' systemctl restart plugin_loader.service".to_string(),
+                    Some("it's secret\n".to_string())
+                ),
+                (
+                    "sudo -n chmod -R ug+rw '/home/deck/homebrew/'".to_string(),
+                    None
+                ),
+                (
+                    "sudo -S -p '' fail".to_string(),
+                    Some("it's secret\n".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn quotes_for_shell() {
+        assert_eq!(shell_quote("/home/deck"), "'/home/deck'");
+        assert_eq!(shell_quote("it's; rm -rf /"), r"'it'\''s; rm -rf /'");
+    }
+}
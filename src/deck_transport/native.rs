@@ -0,0 +1,770 @@
+use anyhow::{anyhow, Context, Result};
+use dirs::home_dir;
+use log::{debug, info, warn};
+use ssh2::{
+    CheckResult, ExtendedData, FileStat, KnownHostFileKind, OpenFlags, OpenType, Session, Sftp,
+};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use walkdir::WalkDir;
+
+use super::{DeckConnection, Transport};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Permissions of everything synced to the Deck, like `rsync --chmod=D0755,F0755`
+const SYNC_MODE: u32 = 0o755;
+
+/// Talks SSH and SFTP to the Deck directly, without needing ssh or rsync installed
+pub struct NativeTransport {
+    session: Session,
+    sftp: Sftp,
+    connection: DeckConnection,
+    description: String,
+}
+
+impl NativeTransport {
+    pub fn connect(connection: &DeckConnection) -> Result<Self> {
+        let host = &connection.host;
+        let port = connection.port;
+
+        let address = (host.as_str(), port)
+            .to_socket_addrs()
+            .with_context(|| format!("Could not resolve {}", host))?
+            .next()
+            .ok_or_else(|| anyhow!("Could not resolve {}", host))?;
+        let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)
+            .with_context(|| format!("Could not connect to {}:{}", host, port))?;
+
+        let mut session = Session::new()?;
+        session.set_tcp_stream(stream);
+        session
+            .handshake()
+            .with_context(|| format!("SSH handshake with {} failed", host))?;
+
+        Self::verify_host_key(&session, connection)?;
+        Self::authenticate(&session, connection)?;
+
+        let sftp = session.sftp().context("Could not start SFTP on the Deck")?;
+
+        Ok(Self {
+            session,
+            sftp,
+            connection: connection.clone(),
+            description: format!("SSH to {}", connection),
+        })
+    }
+
+    /// Checks the Deck's host key against `~/.ssh/known_hosts`, like ssh does.
+    fn verify_host_key(session: &Session, connection: &DeckConnection) -> Result<()> {
+        let (host, port, user) = (&connection.host, connection.port, &connection.user);
+        let (key, _) = session
+            .host_key()
+            .ok_or_else(|| anyhow!("{} did not send a host key", host))?;
+
+        let mut known_hosts = session.known_hosts()?;
+        let file = home_dir().unwrap_or_default().join(".ssh/known_hosts");
+        if file.exists() {
+            known_hosts
+                .read_file(&file, KnownHostFileKind::OpenSSH)
+                .with_context(|| format!("Could not read {}", file.display()))?;
+        }
+
+        match known_hosts.check_port(host, port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::NotFound => Err(anyhow!(
+                "{host} is not in {}. Connect once with `ssh -p {port} {user}@{host}` to check and save its host key.",
+                file.display()
+            )),
+            CheckResult::Mismatch => Err(anyhow!(
+                "The host key of {host} does not match the one in {}. If the Deck was reinstalled, remove the old key with `ssh-keygen -R {host}`, otherwise someone may be intercepting the connection.",
+                file.display()
+            )),
+            CheckResult::Failure => Err(anyhow!("Could not check the host key of {}", host)),
+        }
+    }
+
+    /// Tries the key, then the SSH agent, then the password.
+    fn authenticate(session: &Session, connection: &DeckConnection) -> Result<()> {
+        let user = &connection.user;
+        let mut failures = vec![];
+
+        if let Some(key) = &connection.key {
+            match session.userauth_pubkey_file(user, None, key, None) {
+                Ok(()) => return Ok(()),
+                Err(err) => failures.push(format!("key {}: {}", key.display(), err)),
+            }
+        }
+
+        match session.userauth_agent(user) {
+            Ok(()) => return Ok(()),
+            Err(err) => failures.push(format!("agent: {}", err)),
+        }
+
+        if !connection.password.is_empty() {
+            match session.userauth_password(user, &connection.password) {
+                Ok(()) => return Ok(()),
+                Err(err) => failures.push(format!("password: {}", err)),
+            }
+        }
+
+        Err(anyhow!(
+            "Could not log in to the Deck as {}:\n  - {}",
+            user,
+            failures.join("\n  - ")
+        ))
+    }
+
+    fn check_status(status: i32) -> Result<()> {
+        match status {
+            0 => Ok(()),
+            status => Err(anyhow!("Remote command exited with status {}", status)),
+        }
+    }
+
+    /// Hidden siblings of `remote` to upload a new copy into, and to move the
+    /// installed copy aside to while swapping them.
+    fn staging_paths(remote: &Path) -> Result<(PathBuf, PathBuf)> {
+        let name = remote
+            .file_name()
+            .ok_or_else(|| anyhow!("Cannot sync to `{}`", remote.display()))?
+            .to_string_lossy();
+
+        Ok((
+            remote.with_file_name(format!(".{}.decky-new", name)),
+            remote.with_file_name(format!(".{}.decky-old", name)),
+        ))
+    }
+}
+
+impl Transport for NativeTransport {
+    fn name(&self) -> &str {
+        &self.description
+    }
+
+    fn connection(&self) -> &DeckConnection {
+        &self.connection
+    }
+
+    fn run(&self, command: &str, input: Option<&str>) -> Result<()> {
+        let mut channel = self.session.channel_session()?;
+        // Reading stdout and stderr one after the other could block on a full stderr
+        channel.handle_extended_data(ExtendedData::Merge)?;
+        channel.exec(command)?;
+
+        if let Some(input) = input {
+            channel.write_all(input.as_bytes())?;
+        }
+        channel.send_eof()?;
+
+        // Logged as it arrives, so a command that hangs does not look idle
+        for line in BufReader::new(&mut channel).split(b'\n') {
+            info!("{}", String::from_utf8_lossy(&line?).trim_end());
+        }
+        channel.wait_close()?;
+
+        Self::check_status(channel.exit_status()?)
+    }
+
+    fn create_dir_all(&self, path: &str) -> Result<()> {
+        self.sftp.create_dir_all(Path::new(path))
+    }
+
+    fn sync_dir(&self, local: &Path, remote: &str) -> Result<()> {
+        self.sftp.sync_dir(local, Path::new(remote))
+    }
+}
+
+/// The file operations syncing needs, so it can be tested without a Deck
+trait RemoteFs {
+    fn exists(&self, path: &Path) -> bool;
+    /// Entries directly inside `path`, and whether each is a directory
+    fn read_dir(&self, path: &Path) -> Result<Vec<(PathBuf, bool)>>;
+    fn mkdir(&self, path: &Path) -> Result<()>;
+    fn write(&self, path: &Path, contents: &mut dyn Read) -> Result<()>;
+    /// Sets the synced permissions regardless of the remote umask.
+    fn chmod(&self, path: &Path) -> Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+    fn unlink(&self, path: &Path) -> Result<()>;
+    fn rmdir(&self, path: &Path) -> Result<()>;
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        for (entry, is_dir) in self.read_dir(path)? {
+            match is_dir {
+                true => self.remove_dir_all(&entry)?,
+                false => self.unlink(&entry)?,
+            }
+        }
+
+        self.rmdir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        for dir in path.ancestors().collect::<Vec<_>>().into_iter().rev() {
+            if dir.as_os_str().is_empty() || self.exists(dir) {
+                continue;
+            }
+
+            self.mkdir(dir)
+                .with_context(|| format!("Could not create {} on the Deck", dir.display()))?;
+        }
+
+        Ok(())
+    }
+
+    fn upload_dir(&self, local: &Path, remote: &Path) -> Result<()> {
+        for entry in WalkDir::new(local).sort_by_file_name() {
+            let entry = entry?;
+            let target = match entry.path().strip_prefix(local)? {
+                relative if relative.as_os_str().is_empty() => remote.to_path_buf(),
+                relative => remote.join(relative),
+            };
+
+            match entry.file_type().is_dir() {
+                true => self.mkdir(&target),
+                false => File::open(entry.path())
+                    .map_err(Into::into)
+                    .and_then(|mut file| self.write(&target, &mut file)),
+            }
+            .and_then(|_| self.chmod(&target))
+            .with_context(|| format!("Could not upload {} to the Deck", target.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Uploads next to the installed copy and swaps the two once everything is
+    /// there, so a failed upload leaves the installed copy untouched.
+    fn sync_dir(&self, local: &Path, remote: &Path) -> Result<()> {
+        let (staging, previous) = NativeTransport::staging_paths(remote)?;
+
+        // Left behind by an interrupted deploy
+        for leftover in [&staging, &previous] {
+            if self.exists(leftover) {
+                debug!("Removing {}", leftover.display());
+                self.remove_dir_all(leftover).with_context(|| {
+                    format!("Could not remove {} on the Deck", leftover.display())
+                })?;
+            }
+        }
+
+        self.upload_dir(local, &staging)?;
+
+        let installed = self.exists(remote);
+        if installed {
+            self.rename(remote, &previous).with_context(|| {
+                format!("Could not move {} aside on the Deck", remote.display())
+            })?;
+        }
+
+        if let Err(err) = self.rename(&staging, remote) {
+            if installed {
+                if let Err(err) = self.rename(&previous, remote) {
+                    warn!(
+                        "Could not move {} back to {}: {}",
+                        previous.display(),
+                        remote.display(),
+                        err
+                    );
+                }
+            }
+            return Err(err).with_context(|| {
+                format!(
+                    "Could not move the new copy into {} on the Deck",
+                    remote.display()
+                )
+            });
+        }
+
+        if installed {
+            debug!("Removing {}", previous.display());
+            self.remove_dir_all(&previous).with_context(|| {
+                format!(
+                    "Could not remove the old copy at {} on the Deck",
+                    previous.display()
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RemoteFs for Sftp {
+    fn exists(&self, path: &Path) -> bool {
+        self.lstat(path).is_ok()
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<(PathBuf, bool)>> {
+        Ok(self
+            .readdir(path)?
+            .into_iter()
+            .map(|(entry, stat)| (entry, stat.is_dir()))
+            .collect())
+    }
+
+    fn mkdir(&self, path: &Path) -> Result<()> {
+        Ok(Sftp::mkdir(self, path, SYNC_MODE as i32)?)
+    }
+
+    fn write(&self, path: &Path, contents: &mut dyn Read) -> Result<()> {
+        let mut file = self.open_mode(
+            path,
+            OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+            SYNC_MODE as i32,
+            OpenType::File,
+        )?;
+        io::copy(contents, &mut file)?;
+
+        Ok(())
+    }
+
+    fn chmod(&self, path: &Path) -> Result<()> {
+        self.setstat(
+            path,
+            FileStat {
+                size: None,
+                uid: None,
+                gid: None,
+                perm: Some(SYNC_MODE),
+                atime: None,
+                mtime: None,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        Ok(Sftp::rename(self, from, to, None)?)
+    }
+
+    fn unlink(&self, path: &Path) -> Result<()> {
+        Ok(Sftp::unlink(self, path)?)
+    }
+
+    fn rmdir(&self, path: &Path) -> Result<()> {
+        Ok(Sftp::rmdir(self, path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, collections::BTreeMap, fs, net::TcpListener};
+
+    /// In-memory remote, with directories as None
+    #[derive(Default)]
+    struct FakeFs {
+        entries: RefCell<BTreeMap<PathBuf, Option<Vec<u8>>>>,
+        /// Operation and first path that fail, e.g. `("rename", "/a")`
+        failing: Option<(&'static str, PathBuf)>,
+    }
+
+    impl FakeFs {
+        fn with(entries: &[(&str, Option<&str>)]) -> Self {
+            let fs = FakeFs::default();
+            for (path, contents) in entries {
+                fs.entries.borrow_mut().insert(
+                    PathBuf::from(path),
+                    contents.map(|contents| contents.as_bytes().to_vec()),
+                );
+            }
+            fs
+        }
+
+        fn paths(&self) -> Vec<String> {
+            self.entries
+                .borrow()
+                .keys()
+                .map(|path| path.display().to_string())
+                .collect()
+        }
+
+        fn contents(&self, path: &str) -> Option<String> {
+            self.entries
+                .borrow()
+                .get(Path::new(path))
+                .cloned()
+                .flatten()
+                .map(|contents| String::from_utf8(contents).unwrap())
+        }
+
+        fn failing(mut self, operation: &'static str, path: &str) -> Self {
+            self.failing = Some((operation, path.into()));
+            self
+        }
+
+        fn check(&self, operation: &str, path: &Path) -> Result<()> {
+            match &self.failing {
+                Some((failing, failing_path)) if *failing == operation && failing_path == path => {
+                    Err(anyhow!("{} {} failed", operation, path.display()))
+                }
+                _ => Ok(()),
+            }
+        }
+
+        fn check_parent(&self, path: &Path) -> Result<()> {
+            let parent = path.parent().unwrap();
+            match parent == Path::new("/") || self.entries.borrow().get(parent) == Some(&None) {
+                true => Ok(()),
+                false => Err(anyhow!("no directory {}", parent.display())),
+            }
+        }
+    }
+
+    impl RemoteFs for FakeFs {
+        fn exists(&self, path: &Path) -> bool {
+            path == Path::new("/") || self.entries.borrow().contains_key(path)
+        }
+
+        fn read_dir(&self, path: &Path) -> Result<Vec<(PathBuf, bool)>> {
+            Ok(self
+                .entries
+                .borrow()
+                .iter()
+                .filter(|(entry, _)| entry.parent() == Some(path))
+                .map(|(entry, contents)| (entry.clone(), contents.is_none()))
+                .collect())
+        }
+
+        fn mkdir(&self, path: &Path) -> Result<()> {
+            self.check("mkdir", path)?;
+            self.check_parent(path)?;
+            if self.exists(path) {
+                return Err(anyhow!("{} exists", path.display()));
+            }
+            self.entries.borrow_mut().insert(path.to_path_buf(), None);
+            Ok(())
+        }
+
+        fn write(&self, path: &Path, contents: &mut dyn Read) -> Result<()> {
+            self.check("write", path)?;
+            self.check_parent(path)?;
+            let mut buffer = vec![];
+            contents.read_to_end(&mut buffer)?;
+            self.entries
+                .borrow_mut()
+                .insert(path.to_path_buf(), Some(buffer));
+            Ok(())
+        }
+
+        fn chmod(&self, path: &Path) -> Result<()> {
+            self.check("chmod", path)?;
+            match self.exists(path) {
+                true => Ok(()),
+                false => Err(anyhow!("no {}", path.display())),
+            }
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+            self.check("rename", from)?;
+            if !self.exists(from) || self.exists(to) {
+                return Err(anyhow!(
+                    "cannot rename {} to {}",
+                    from.display(),
+                    to.display()
+                ));
+            }
+
+            let mut entries = self.entries.borrow_mut();
+            let moved: Vec<PathBuf> = entries
+                .keys()
+                .filter(|path| path.starts_with(from))
+                .cloned()
+                .collect();
+            for path in moved {
+                let contents = entries.remove(&path).unwrap();
+                let relative = path.strip_prefix(from).unwrap();
+                entries.insert(
+                    to.components().chain(relative.components()).collect(),
+                    contents,
+                );
+            }
+            Ok(())
+        }
+
+        fn unlink(&self, path: &Path) -> Result<()> {
+            self.check("unlink", path)?;
+            match self.entries.borrow_mut().remove(path) {
+                Some(Some(_)) => Ok(()),
+                _ => Err(anyhow!("no file {}", path.display())),
+            }
+        }
+
+        fn rmdir(&self, path: &Path) -> Result<()> {
+            self.check("rmdir", path)?;
+            if !self.read_dir(path)?.is_empty() {
+                return Err(anyhow!("{} is not empty", path.display()));
+            }
+            match self.entries.borrow_mut().remove(path) {
+                Some(None) => Ok(()),
+                _ => Err(anyhow!("no directory {}", path.display())),
+            }
+        }
+    }
+
+    /// A built plugin with a nested file
+    fn local_plugin() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("dist")).unwrap();
+        fs::write(dir.path().join("dist/index.js"), "new").unwrap();
+        fs::write(dir.path().join("plugin.json"), "{}").unwrap();
+        dir
+    }
+
+    const PLUGIN: &str = "/plugins/Test";
+
+    fn connection(port: u16) -> DeckConnection {
+        DeckConnection {
+            host: "127.0.0.1".into(),
+            port,
+            user: "deck".into(),
+            key: None,
+            password: "".into(),
+        }
+    }
+
+    #[test]
+    fn checks_exit_status() {
+        NativeTransport::check_status(0).unwrap();
+        assert_eq!(
+            NativeTransport::check_status(127).unwrap_err().to_string(),
+            "Remote command exited with status 127"
+        );
+    }
+
+    #[test]
+    fn stages_next_to_the_installed_copy() {
+        let (staging, previous) =
+            NativeTransport::staging_paths(Path::new("/home/deck/homebrew/plugins/My Plugin"))
+                .unwrap();
+
+        assert_eq!(
+            staging,
+            Path::new("/home/deck/homebrew/plugins/.My Plugin.decky-new")
+        );
+        assert_eq!(
+            previous,
+            Path::new("/home/deck/homebrew/plugins/.My Plugin.decky-old")
+        );
+        assert!(NativeTransport::staging_paths(Path::new("/")).is_err());
+    }
+
+    #[test]
+    fn creates_missing_directories() {
+        let remote = FakeFs::with(&[("/home", None)]);
+
+        remote
+            .create_dir_all(Path::new("/home/deck/homebrew/plugins"))
+            .unwrap();
+        remote
+            .create_dir_all(Path::new("/home/deck/homebrew"))
+            .unwrap();
+
+        assert_eq!(
+            remote.paths(),
+            [
+                "/home",
+                "/home/deck",
+                "/home/deck/homebrew",
+                "/home/deck/homebrew/plugins"
+            ]
+        );
+    }
+
+    #[test]
+    fn removes_directories_recursively() {
+        let remote = FakeFs::with(&[
+            ("/plugins", None),
+            (PLUGIN, None),
+            ("/plugins/Test/dist", None),
+            ("/plugins/Test/dist/index.js", Some("old")),
+            ("/plugins/Test/main.py", Some("old")),
+        ]);
+
+        remote.remove_dir_all(Path::new(PLUGIN)).unwrap();
+
+        assert_eq!(remote.paths(), ["/plugins"]);
+    }
+
+    #[test]
+    fn syncs_a_new_plugin() {
+        let local = local_plugin();
+        let remote = FakeFs::with(&[("/plugins", None)]);
+
+        remote.sync_dir(local.path(), Path::new(PLUGIN)).unwrap();
+
+        assert_eq!(
+            remote.paths(),
+            [
+                "/plugins",
+                PLUGIN,
+                "/plugins/Test/dist",
+                "/plugins/Test/dist/index.js",
+                "/plugins/Test/plugin.json"
+            ]
+        );
+        assert_eq!(
+            remote.contents("/plugins/Test/dist/index.js").as_deref(),
+            Some("new")
+        );
+    }
+
+    #[test]
+    fn swaps_in_the_new_copy() {
+        let local = local_plugin();
+        let remote = FakeFs::with(&[
+            ("/plugins", None),
+            (PLUGIN, None),
+            ("/plugins/Test/dist", None),
+            ("/plugins/Test/dist/index.js", Some("old")),
+            ("/plugins/Test/stale.py", Some("old")),
+            // From an interrupted deploy
+            ("/plugins/.Test.decky-new", None),
+            ("/plugins/.Test.decky-new/half", Some("")),
+        ]);
+
+        remote.sync_dir(local.path(), Path::new(PLUGIN)).unwrap();
+
+        assert_eq!(
+            remote.paths(),
+            [
+                "/plugins",
+                PLUGIN,
+                "/plugins/Test/dist",
+                "/plugins/Test/dist/index.js",
+                "/plugins/Test/plugin.json"
+            ]
+        );
+        assert_eq!(
+            remote.contents("/plugins/Test/dist/index.js").as_deref(),
+            Some("new")
+        );
+    }
+
+    #[test]
+    fn keeps_the_installed_copy_when_a_sync_fails() {
+        let local = local_plugin();
+        let installed = || {
+            FakeFs::with(&[
+                ("/plugins", None),
+                (PLUGIN, None),
+                ("/plugins/Test/main.py", Some("old")),
+            ])
+        };
+
+        let remote = installed().failing("write", "/plugins/.Test.decky-new/plugin.json");
+        let error = remote
+            .sync_dir(local.path(), Path::new(PLUGIN))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Could not upload /plugins/.Test.decky-new/plugin.json to the Deck"
+        );
+        assert_eq!(
+            remote.contents("/plugins/Test/main.py").as_deref(),
+            Some("old")
+        );
+
+        // The old copy is moved back when the new one cannot be moved in
+        let remote = installed().failing("rename", "/plugins/.Test.decky-new");
+        let error = remote
+            .sync_dir(local.path(), Path::new(PLUGIN))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Could not move the new copy into /plugins/Test on the Deck"
+        );
+        assert_eq!(
+            remote.contents("/plugins/Test/main.py").as_deref(),
+            Some("old")
+        );
+        assert!(!remote.exists(Path::new("/plugins/.Test.decky-old")));
+
+        // The next sync cleans up after the failed one
+        let remote = FakeFs {
+            failing: None,
+            ..remote
+        };
+        remote.sync_dir(local.path(), Path::new(PLUGIN)).unwrap();
+        assert_eq!(remote.contents("/plugins/Test/main.py"), None);
+        assert!(!remote.exists(Path::new("/plugins/.Test.decky-new")));
+    }
+
+    #[test]
+    fn reports_connection_failures() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || drop(listener.accept()));
+
+        let error = NativeTransport::connect(&connection(port))
+            .err()
+            .unwrap()
+            .to_string();
+        assert_eq!(error, "SSH handshake with 127.0.0.1 failed");
+        server.join().unwrap();
+
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let error = NativeTransport::connect(&connection(port))
+            .err()
+            .unwrap()
+            .to_string();
+        assert_eq!(error, format!("Could not connect to 127.0.0.1:{}", port));
+    }
+
+    /// Needs a real SSH server, such as a Deck or a local sshd whose host key
+    /// is known: `DECKY_TEST_DECK=deck@127.0.0.1:22 cargo test -- --ignored`.
+    /// Logs in with `DECKY_TEST_DECK_KEY`, the agent or `DECKY_TEST_DECK_PASSWORD`.
+    #[test]
+    #[ignore]
+    fn deploys_over_ssh() {
+        let deck = std::env::var("DECKY_TEST_DECK").expect("DECKY_TEST_DECK is not set");
+        let (user, address) = deck.split_once('@').unwrap();
+        let (host, port) = address.split_once(':').unwrap_or((address, "22"));
+        let transport = NativeTransport::connect(&DeckConnection {
+            host: host.into(),
+            port: port.parse().unwrap(),
+            user: user.into(),
+            key: std::env::var_os("DECKY_TEST_DECK_KEY").map(PathBuf::from),
+            password: std::env::var("DECKY_TEST_DECK_PASSWORD").unwrap_or_default(),
+        })
+        .unwrap();
+
+        transport
+            .run(r#"test "$(cat)" = secret"#, Some("secret"))
+            .unwrap();
+        assert_eq!(
+            transport.run("exit 3", None).unwrap_err().to_string(),
+            "Remote command exited with status 3"
+        );
+
+        let root = format!("/tmp/decky-test-{}", std::process::id());
+        let plugin = format!("{}/plugins/Test", root);
+        let local = local_plugin();
+        transport
+            .create_dir_all(&format!("{}/plugins", root))
+            .unwrap();
+        transport.sync_dir(local.path(), &plugin).unwrap();
+        fs::write(local.path().join("dist/index.js"), "newer").unwrap();
+        transport.sync_dir(local.path(), &plugin).unwrap();
+
+        let synced = transport.run(
+            &format!(
+                "test \"$(cat {plugin}/dist/index.js)\" = newer && test ! -e {root}/plugins/.Test.decky-old && test ! -e {root}/plugins/.Test.decky-new"
+            ),
+            None,
+        );
+        transport.run(&format!("rm -rf {}", root), None).unwrap();
+        synced.unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+use anyhow::{anyhow, Context, Result};
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, ExitStatus, Stdio},
+};
+
+use super::{shell_quote, DeckConnection, Transport};
+
+/// Runs the `ssh` and `rsync` programs installed on the host
+pub struct OpenSshTransport {
+    connection: DeckConnection,
+    description: String,
+}
+
+impl OpenSshTransport {
+    pub fn new(connection: &DeckConnection) -> Self {
+        Self {
+            connection: connection.clone(),
+            description: format!("ssh and rsync to {}", connection),
+        }
+    }
+
+    fn run_program(cmd: &mut Command, input: Option<&str>) -> Result<()> {
+        let program = cmd.get_program().to_string_lossy().to_string();
+        let mut child = cmd
+            .stdin(match input {
+                Some(_) => Stdio::piped(),
+                None => Stdio::null(),
+            })
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("Could not run `{}`. Is it installed?", program))?;
+
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin.write_all(input.as_bytes())?;
+        }
+
+        Self::check_status(&program, child.wait()?)
+    }
+
+    fn check_status(program: &str, status: ExitStatus) -> Result<()> {
+        match status.success() {
+            true => Ok(()),
+            false => Err(anyhow!(
+                "`{}` exited with status {}",
+                program,
+                status.code().unwrap_or(-1)
+            )),
+        }
+    }
+
+    fn ssh_command(&self, command: &str) -> Command {
+        let mut cmd = Command::new("ssh");
+        cmd.args(self.connection.ssh_options())
+            .arg(self.connection.destination())
+            .arg(command);
+        cmd
+    }
+
+    /// rsync invocation replacing `remote` with `local`, which has the same name
+    fn rsync_command(&self, local: &Path, remote: &str) -> Result<Command> {
+        let (parent, _) = remote
+            .trim_end_matches('/')
+            .rsplit_once('/')
+            .ok_or_else(|| anyhow!("Cannot sync to `{}`", remote))?;
+
+        let mut cmd = Command::new("rsync");
+        cmd.arg("-azp")
+            .arg("--delete")
+            .arg("--chmod=D0755,F0755")
+            // Pass the remote path as is instead of through the remote shell
+            .arg("--protect-args")
+            .arg("--rsh")
+            .arg(self.connection.rsync_shell())
+            .arg(local)
+            .arg(format!("{}:{}", self.connection.destination(), parent));
+        Ok(cmd)
+    }
+}
+
+impl Transport for OpenSshTransport {
+    fn name(&self) -> &str {
+        &self.description
+    }
+
+    fn connection(&self) -> &DeckConnection {
+        &self.connection
+    }
+
+    fn run(&self, command: &str, input: Option<&str>) -> Result<()> {
+        Self::run_program(&mut self.ssh_command(command), input)
+    }
+
+    fn create_dir_all(&self, path: &str) -> Result<()> {
+        self.run(&format!("mkdir -p {}", shell_quote(path)), None)
+    }
+
+    fn sync_dir(&self, local: &Path, remote: &str) -> Result<()> {
+        Self::run_program(&mut self.rsync_command(local, remote)?, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn transport() -> OpenSshTransport {
+        OpenSshTransport::new(&DeckConnection {
+            host: "steamdeck".into(),
+            port: 2222,
+            user: "deck".into(),
+            key: Some(PathBuf::from("/keys/my deck")),
+            password: "".into(),
+        })
+    }
+
+    fn args(cmd: &Command) -> Vec<String> {
+        cmd.get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn builds_ssh_command() {
+        let cmd = transport().ssh_command("mkdir -p '/home/deck/homebrew'");
+
+        assert_eq!(cmd.get_program(), "ssh");
+        assert_eq!(
+            args(&cmd),
+            [
+                "-p",
+                "2222",
+                "-i",
+                "/keys/my deck",
+                "deck@steamdeck",
+                "mkdir -p '/home/deck/homebrew'"
+            ]
+        );
+    }
+
+    #[test]
+    fn builds_rsync_command() {
+        let cmd = transport()
+            .rsync_command(
+                Path::new("/tmp/out/My Plugin"),
+                "/home/deck/homebrew/plugins/My Plugin/",
+            )
+            .unwrap();
+
+        assert_eq!(cmd.get_program(), "rsync");
+        assert_eq!(
+            args(&cmd),
+            [
+                "-azp",
+                "--delete",
+                "--chmod=D0755,F0755",
+                "--protect-args",
+                "--rsh",
+                "ssh '-p' '2222' '-i' '/keys/my deck'",
+                "/tmp/out/My Plugin",
+                "deck@steamdeck:/home/deck/homebrew/plugins"
+            ]
+        );
+
+        assert!(transport()
+            .rsync_command(Path::new("/tmp/out/plugin"), "plugin")
+            .is_err());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn checks_exit_status() {
+        let run = |script: &str, input| {
+            OpenSshTransport::run_program(Command::new("sh").args(["-c", script]), input)
+        };
+
+        run("true", None).unwrap();
+        run(r#"read line && [ "$line" = secret ]"#, Some("secret\n")).unwrap();
+        assert_eq!(
+            run("exit 3", None).unwrap_err().to_string(),
+            "`sh` exited with status 3"
+        );
+        assert!(
+            OpenSshTransport::run_program(&mut Command::new("decky-no-such-program"), None)
+                .unwrap_err()
+                .to_string()
+                .starts_with("Could not run `decky-no-such-program`")
+        );
+    }
+}
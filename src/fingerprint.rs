@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+/// Directories that never affect a build, wherever they appear
+const IGNORED_NAMES: [&str; 3] = [".git", "node_modules", "__pycache__"];
+
+/// Hash of everything a build stage depends on
+pub struct Fingerprint(Sha256);
+
+impl Fingerprint {
+    pub fn new(stage: &str) -> Self {
+        let mut fingerprint = Self(Sha256::new());
+        fingerprint.value("stage", stage);
+        fingerprint
+    }
+
+    pub fn value(&mut self, name: &str, value: impl Display) -> &mut Self {
+        self.0.update(format!("{}={}\0", name, value));
+        self
+    }
+
+    pub fn file(&mut self, name: &str, path: &Path) -> Result<&mut Self> {
+        match fs::File::open(path) {
+            Ok(mut file) => {
+                self.value(name, "file");
+                io::copy(&mut file, &mut self.0)?;
+                self.0.update(b"\0");
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                self.value(name, "missing");
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("Could not read {}", path.display()))
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Hashes the names, contents and permissions of everything under `root`,
+    /// leaving out `exclude`d paths.
+    pub fn tree(&mut self, root: &Path, exclude: &[PathBuf]) -> Result<&mut Self> {
+        if !root.exists() {
+            self.value(&root.to_string_lossy(), "missing");
+            return Ok(self);
+        }
+
+        let entries = WalkDir::new(root)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| {
+                !IGNORED_NAMES.contains(&entry.file_name().to_string_lossy().as_ref())
+                    && !exclude.iter().any(|path| entry.path() == path)
+            });
+
+        for entry in entries {
+            let entry = entry?;
+            let name = entry
+                .path()
+                .strip_prefix(root)
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            let metadata = entry.metadata()?;
+
+            #[cfg(target_family = "unix")]
+            self.value(
+                &format!("{}:mode", name),
+                std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o777,
+            );
+
+            if entry.path_is_symlink() {
+                self.value(&name, fs::read_link(entry.path())?.display());
+            } else if metadata.is_file() {
+                self.file(&name, entry.path())?;
+            } else {
+                self.value(&name, "dir");
+            }
+        }
+
+        Ok(self)
+    }
+
+    pub fn finish(&self) -> String {
+        format!("{:x}", self.0.clone().finalize())
+    }
+}
+
+/// Fingerprints of the stages that last completed in a build directory
+pub struct Fingerprints {
+    dir: PathBuf,
+}
+
+impl Fingerprints {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn matches(&self, stage: &str, fingerprint: &Fingerprint) -> bool {
+        fs::read_to_string(self.dir.join(stage))
+            .is_ok_and(|recorded| recorded.trim() == fingerprint.finish())
+    }
+
+    /// Forgets `stage`, so it is rebuilt even if a later run stops half way.
+    pub fn invalidate(&self, stage: &str) -> Result<()> {
+        match fs::remove_file(self.dir.join(stage)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record(&self, stage: &str, fingerprint: &Fingerprint) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.dir.join(stage), fingerprint.finish())
+            .with_context(|| format!("Could not record the {} fingerprint", stage))
+    }
+}
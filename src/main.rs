@@ -1,9 +1,16 @@
 //#![feature(exit_status_error)]
+mod cache;
 mod cli;
+mod config;
 mod container_engine;
+mod deck_transport;
+mod fingerprint;
+mod output;
 mod plugin;
+mod remote_binary;
+mod util;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::Parser;
 use cli::{Command, CLI};
 use flexi_logger::Logger;
@@ -17,7 +24,19 @@ async fn main() -> Result<()> {
 
     let cli = CLI::parse();
 
-    match &cli.command {
-        Command::Plugin(args) => cli::plugin::parse(args).await,
+    let run = async {
+        match &cli.command {
+            Command::Plugin(args) => cli::plugin::parse(args).await,
+            Command::Cache(args) => cli::cache::parse(args),
+            Command::Deck(args) => cli::deck::parse(args),
+        }
+    };
+
+    tokio::select! {
+        result = run => result,
+        _ = tokio::signal::ctrl_c() => {
+            container_engine::shutdown().await;
+            Err(anyhow!("Interrupted"))
+        }
     }
 }
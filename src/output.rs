@@ -0,0 +1,213 @@
+use anyhow::{Context, Result};
+use std::{
+    fs::File,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// ANSI colors handed out to stages in order
+const STAGE_COLORS: [u8; 5] = [36, 35, 33, 32, 34];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Which streams stage prefixes are colored on, each decided by whether it
+/// is a terminal, so redirecting one does not write escapes or lose colors
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Colors {
+    stdout: bool,
+    stderr: bool,
+}
+
+impl Colors {
+    fn detect() -> Self {
+        let enabled = std::env::var_os("NO_COLOR").is_none();
+
+        Self {
+            stdout: enabled && io::stdout().is_terminal(),
+            stderr: enabled && io::stderr().is_terminal(),
+        }
+    }
+
+    fn enabled(&self, stream: Stream) -> bool {
+        match stream {
+            Stream::Stdout => self.stdout,
+            Stream::Stderr => self.stderr,
+        }
+    }
+}
+
+type LogFile = Arc<Mutex<File>>;
+
+fn create_log_file(path: &Path) -> Result<LogFile> {
+    Ok(Arc::new(Mutex::new(File::create(path).with_context(
+        || format!("Could not create log file {}", path.display()),
+    )?)))
+}
+
+/// Log file of a single stage next to `log_file`, e.g. `build.frontend.log` for `build.log`
+pub fn stage_log_path(log_file: &Path, stage: &str) -> PathBuf {
+    let stem = log_file.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = log_file
+        .extension()
+        .map(|extension| extension.to_string_lossy())
+        .unwrap_or("log".into());
+
+    log_file.with_file_name(format!("{}.{}.{}", stem, stage, extension))
+}
+
+/// How command output of a build is shown and saved, shared by all its stages
+#[derive(Clone, Default)]
+pub struct BuildOutput {
+    quiet: bool,
+    colors: Colors,
+    log_file: Option<(PathBuf, LogFile)>,
+    stages: Arc<AtomicUsize>,
+}
+
+impl BuildOutput {
+    /// In `quiet` mode nothing is printed while stages run, and only the log
+    /// of a failing stage is shown. With `log_file`, the output of all stages
+    /// is also written there, and the full output of each stage to its own
+    /// file next to it, see [`stage_log_path`].
+    pub fn new(quiet: bool, log_file: Option<&Path>) -> Result<Self> {
+        let log_file = match log_file {
+            Some(path) => Some((path.to_path_buf(), create_log_file(path)?)),
+            None => None,
+        };
+
+        Ok(Self {
+            quiet,
+            colors: Colors::detect(),
+            log_file,
+            stages: Default::default(),
+        })
+    }
+
+    pub fn stage(&self, name: &str) -> Result<StageLog> {
+        let index = self.stages.fetch_add(1, Ordering::Relaxed);
+
+        let (log_file, stage_file) = match &self.log_file {
+            Some((path, file)) => (
+                Some(file.clone()),
+                Some(create_log_file(&stage_log_path(path, name))?),
+            ),
+            None => (None, None),
+        };
+
+        Ok(StageLog {
+            name: name.to_string(),
+            color: STAGE_COLORS[index % STAGE_COLORS.len()],
+            colors: self.colors,
+            quiet: self.quiet,
+            log_file,
+            stage_file,
+            captured: Default::default(),
+        })
+    }
+}
+
+/// Output of one build stage, e.g. `frontend`
+#[derive(Clone)]
+pub struct StageLog {
+    name: String,
+    color: u8,
+    colors: Colors,
+    quiet: bool,
+    log_file: Option<LogFile>,
+    /// Full output of only this stage, without prefixes
+    stage_file: Option<LogFile>,
+    captured: Arc<Mutex<Vec<(Stream, String)>>>,
+}
+
+impl StageLog {
+    fn prefix(&self, stream: Stream) -> String {
+        match self.colors.enabled(stream) {
+            true => format!("\x1b[{}m[{}]\x1b[0m", self.color, self.name),
+            false => format!("[{}]", self.name),
+        }
+    }
+
+    fn print(&self, stream: Stream, line: &str) {
+        match stream {
+            Stream::Stdout => println!("{} {}", self.prefix(stream), line),
+            Stream::Stderr => eprintln!("{} {}", self.prefix(stream), line),
+        }
+    }
+
+    pub fn line(&self, stream: Stream, line: &str) {
+        if let Some(file) = &self.log_file {
+            let mut file = file.lock().unwrap();
+            writeln!(file, "[{}] {}", self.name, line).ok();
+        }
+        if let Some(file) = &self.stage_file {
+            let mut file = file.lock().unwrap();
+            writeln!(file, "{}", line).ok();
+        }
+
+        if self.quiet {
+            self.captured
+                .lock()
+                .unwrap()
+                .push((stream, line.to_string()));
+        } else {
+            self.print(stream, line);
+        }
+    }
+
+    /// Output held back in quiet mode so far
+    #[cfg(test)]
+    pub fn captured(&self) -> Vec<(Stream, String)> {
+        self.captured.lock().unwrap().clone()
+    }
+
+    /// Shows the output held back in quiet mode, after the stage failed.
+    pub fn replay(&self) {
+        for (stream, line) in self.captured.lock().unwrap().drain(..) {
+            self.print(stream, &line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_stage_logs_after_log_file() {
+        assert_eq!(
+            stage_log_path(Path::new("logs/build.log"), "frontend"),
+            Path::new("logs/build.frontend.log")
+        );
+        assert_eq!(
+            stage_log_path(Path::new("build.txt"), "backend"),
+            Path::new("build.backend.txt")
+        );
+        assert_eq!(
+            stage_log_path(Path::new("build"), "pull"),
+            Path::new("build.pull.log")
+        );
+    }
+
+    #[test]
+    fn colors_each_stream_separately() {
+        let output = BuildOutput {
+            colors: Colors {
+                stdout: true,
+                stderr: false,
+            },
+            ..Default::default()
+        };
+        let log = output.stage("frontend").unwrap();
+
+        assert_eq!(log.prefix(Stream::Stdout), "\x1b[36m[frontend]\x1b[0m");
+        assert_eq!(log.prefix(Stream::Stderr), "[frontend]");
+    }
+}
@@ -1,8 +1,15 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use boolinator::Boolinator;
+use itertools::Itertools;
 use log::info;
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::config::ProjectConfig;
 
 #[derive(Clone)]
 pub enum CustomBackend {
@@ -13,20 +20,86 @@ pub enum CustomBackend {
 #[derive(Clone)]
 pub struct Plugin {
     pub meta: PluginFile,
+    pub config: ProjectConfig,
     #[allow(dead_code)]
     pub root: PathBuf,
     pub custom_backend: CustomBackend,
 }
 
+/// Flags understood by Decky Loader in plugin.json
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub enum Flag {
+    /// Run the plugin's backend as root
+    Root,
+    /// Disabled form of `root`: the leading underscore switches the flag off
+    /// while keeping it in plugin.json
+    DisabledRoot,
+    /// Enable the loader's debugging helpers for the plugin
+    Debug,
+}
+
+impl Flag {
+    pub const ALL: [Flag; 3] = [Flag::Root, Flag::DisabledRoot, Flag::Debug];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Root => "root",
+            Self::DisabledRoot => "_root",
+            Self::Debug => "debug",
+        }
+    }
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Flag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Flag::ALL
+            .into_iter()
+            .find(|flag| flag.as_str() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown flag `{}`, valid flags are: {}",
+                    s,
+                    Flag::ALL.iter().join(", ")
+                )
+            })
+    }
+}
+
+impl TryFrom<String> for Flag {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Flag> for String {
+    fn from(flag: Flag) -> Self {
+        flag.as_str().to_string()
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct PluginFile {
     pub name: String,
     pub author: String,
-
-    // TODO: Use a Vec<Flag> enum
-    pub flags: Vec<String>,
+    pub flags: Vec<Flag>,
 }
 
+impl PluginFile {
+    pub fn requires_root(&self) -> bool {
+        self.flags.contains(&Flag::Root)
+    }
+}
 
 impl Plugin {
     fn find_custom_backend(plugin_root: &Path) -> Result<CustomBackend> {
@@ -61,7 +134,9 @@ impl Plugin {
             .exists()
             .as_result(pluginfile_location, anyhow!("Could not find plugin.json"))
             .and_then(|pluginfile| std::fs::read_to_string(pluginfile).map_err(Into::into))
-            .and_then(|str| serde_json::from_str::<PluginFile>(&str).map_err(Into::into))
+            .and_then(|str| {
+                serde_json::from_str::<PluginFile>(&str).context("Could not parse plugin.json")
+            })
     }
 
     pub fn new(plugin_root: PathBuf) -> Result<Self> {
@@ -69,8 +144,44 @@ impl Plugin {
 
         Ok(Self {
             meta: Plugin::find_pluginfile(&plugin_root)?,
+            config: ProjectConfig::load(&plugin_root)?,
             custom_backend: Plugin::find_custom_backend(&plugin_root)?,
             root: plugin_root.clone(),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pluginfile(flags: &str) -> serde_json::Result<PluginFile> {
+        serde_json::from_str(&format!(
+            r#"{{"name": "Test", "author": "Jane", "flags": {}}}"#,
+            flags
+        ))
+    }
+
+    #[test]
+    fn disabled_root_does_not_require_root() {
+        assert!(pluginfile(r#"["root"]"#).unwrap().requires_root());
+        assert!(pluginfile(r#"["debug", "root"]"#).unwrap().requires_root());
+
+        let disabled = pluginfile(r#"["_root"]"#).unwrap();
+        assert_eq!(disabled.flags, vec![Flag::DisabledRoot]);
+        assert!(!disabled.requires_root());
+        assert!(!pluginfile("[]").unwrap().requires_root());
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        let err = pluginfile(r#"["rooot"]"#).err().unwrap().to_string();
+
+        assert!(err.contains("unknown flag `rooot`"), "{}", err);
+        assert!(
+            err.contains("valid flags are: root, _root, debug"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,472 @@
+use anyhow::{anyhow, Context, Result};
+use boolinator::Boolinator;
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::cache::BlobCache;
+
+/// A prebuilt file listed under `remote_binary` in package.json
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RemoteBinary {
+    pub name: String,
+    pub url: String,
+    pub sha256hash: String,
+}
+
+/// The `remote_binary` section of package.json
+#[derive(Clone, Debug, Default)]
+pub struct RemoteBinaries {
+    pub bundling: bool,
+    pub entries: Vec<RemoteBinary>,
+}
+
+impl RemoteBinary {
+    fn validate(&self) -> Result<()> {
+        if self.name.is_empty()
+            || self.name.contains(['/', '\\'])
+            || self.name == "."
+            || self.name == ".."
+        {
+            return Err(anyhow!("`name` must be a plain file name"));
+        }
+
+        if self.sha256hash.len() != 64 || !self.sha256hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!(
+                "`sha256hash` must be a 64 character hex encoded sha256 digest"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The expected digest, normalized so it can be compared with our own output.
+    pub fn checksum(&self) -> String {
+        self.sha256hash.to_ascii_lowercase()
+    }
+}
+
+impl RemoteBinaries {
+    pub fn from_package_json(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let json: Value = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        Self::from_value(&json)
+    }
+
+    pub fn from_value(json: &Value) -> Result<Self> {
+        let bundling = match &json["remote_binary_bundling"] {
+            Value::Null => false,
+            Value::Bool(bundling) => *bundling,
+            _ => return Err(anyhow!("`remote_binary_bundling` must be a boolean")),
+        };
+
+        let entries = match &json["remote_binary"] {
+            Value::Null => vec![],
+            Value::Array(entries) => entries
+                .iter()
+                .enumerate()
+                .map(|(index, entry)| {
+                    serde_json::from_value::<RemoteBinary>(entry.clone())
+                        .map_err(Into::into)
+                        .and_then(|binary| binary.validate().map(|_| binary))
+                        .with_context(|| format!("Invalid remote_binary[{}] entry", index))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            _ => return Err(anyhow!("`remote_binary` must be an array")),
+        };
+
+        Ok(Self { bundling, entries })
+    }
+}
+
+/// Why a single download attempt failed, and whether trying again could help
+struct AttemptError {
+    error: anyhow::Error,
+    retryable: bool,
+}
+
+impl AttemptError {
+    fn retryable(error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            error: error.into(),
+            retryable: true,
+        }
+    }
+
+    fn fatal(error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            error: error.into(),
+            retryable: false,
+        }
+    }
+}
+
+pub struct Downloader {
+    client: reqwest::Client,
+    pub cache: Option<BlobCache>,
+    pub binaries_dir: Option<PathBuf>,
+    pub offline: bool,
+    pub attempts: u32,
+    pub backoff: Duration,
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            cache: None,
+            binaries_dir: None,
+            offline: false,
+            attempts: 4,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl Downloader {
+    fn partial_path(destination: &Path) -> PathBuf {
+        let mut name = destination.file_name().unwrap_or_default().to_os_string();
+        name.push(".part");
+        destination.with_file_name(name)
+    }
+
+    /// Streams the response body into `partial`, hashing it on the way.
+    async fn attempt(&self, binary: &RemoteBinary, partial: &Path) -> Result<(), AttemptError> {
+        let mut response = self
+            .client
+            .get(&binary.url)
+            .send()
+            .await
+            .map_err(AttemptError::retryable)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error = anyhow!("server responded with {}", status);
+            return Err(match status.is_server_error() || status.as_u16() == 429 {
+                true => AttemptError::retryable(error),
+                false => AttemptError::fatal(error),
+            });
+        }
+
+        let mut file = fs::File::create(partial)
+            .await
+            .with_context(|| format!("Failed to create {}", partial.display()))
+            .map_err(AttemptError::fatal)?;
+        let mut hasher = Sha256::new();
+
+        while let Some(chunk) = response.chunk().await.map_err(AttemptError::retryable)? {
+            hasher.update(&chunk);
+            file.write_all(&chunk).await.map_err(AttemptError::fatal)?;
+        }
+        file.flush().await.map_err(AttemptError::fatal)?;
+
+        let checksum = format!("{:x}", hasher.finalize());
+        if checksum != binary.checksum() {
+            return Err(AttemptError::fatal(anyhow!(
+                "checksum mismatch, expected {} but downloaded file has {}",
+                binary.checksum(),
+                checksum
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn with_cache(cache: BlobCache) -> Self {
+        Self {
+            cache: Some(cache),
+            ..Default::default()
+        }
+    }
+
+    /// Finds an intact local copy of `binary` in the cache or `binaries_dir`,
+    /// without touching the network.
+    pub fn locate(&self, binary: &RemoteBinary) -> Result<Option<PathBuf>> {
+        if let Some(cache) = &self.cache {
+            if let Some(blob) = cache.get(&binary.checksum())? {
+                return Ok(Some(blob));
+            }
+        }
+
+        if let Some(binaries_dir) = &self.binaries_dir {
+            let candidate = binaries_dir.join(&binary.name);
+
+            if candidate.is_file() {
+                if BlobCache::hash_file(&candidate)? == binary.checksum() {
+                    return Ok(Some(candidate));
+                }
+
+                warn!(
+                    "{} does not match the sha256hash of `{}`, ignoring it",
+                    candidate.display(),
+                    binary.name
+                );
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Places `binary` at `destination`, preferring local copies and
+    /// populating the cache when it has to be downloaded.
+    pub async fn fetch(&self, binary: &RemoteBinary, destination: &Path) -> Result<()> {
+        if let Some(local) = self.locate(binary)? {
+            info!(
+                "Using local copy of `{}` from {}",
+                binary.name,
+                local.display()
+            );
+
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::copy(&local, destination).await?;
+            return Ok(());
+        }
+
+        if self.offline {
+            return Err(anyhow!(
+                "`{}` ({}) is not in the download cache or binaries directory, and network access is disabled",
+                binary.name,
+                binary.checksum()
+            ));
+        }
+
+        let Some(cache) = &self.cache else {
+            return self.download(binary, destination).await;
+        };
+
+        self.download(binary, &cache.path_for(&binary.checksum()))
+            .await?;
+
+        cache
+            .copy_to(&binary.checksum(), destination)?
+            .as_result((), anyhow!("`{}` disappeared from the cache", binary.name))
+    }
+
+    /// Downloads `binary` to `destination`, verifying its checksum. The file only
+    /// appears at `destination` once it has been fully downloaded and verified.
+    pub async fn download(&self, binary: &RemoteBinary, destination: &Path) -> Result<()> {
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let partial = Downloader::partial_path(destination);
+        let mut delay = self.backoff;
+
+        for attempt in 1..=self.attempts {
+            info!("Downloading {} (attempt {})", binary.url, attempt);
+
+            match self.attempt(binary, &partial).await {
+                Ok(()) => {
+                    fs::rename(&partial, destination).await?;
+                    info!("File saved to: {:?}", destination);
+                    return Ok(());
+                }
+                Err(AttemptError { error, retryable }) => {
+                    fs::remove_file(&partial).await.ok();
+
+                    if !retryable || attempt == self.attempts {
+                        return Err(error.context(format!(
+                            "Failed to download `{}` from {} after {} attempt(s)",
+                            binary.name, binary.url, attempt
+                        )));
+                    }
+
+                    warn!(
+                        "Downloading `{}` failed: {:#}. Retrying in {:?}",
+                        binary.name, error, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+        }
+
+        Err(anyhow!(
+            "No download attempts were made for `{}`",
+            binary.name
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    const BODY: &[u8] = b"#!/bin/sh\necho hello\n";
+
+    fn body_checksum() -> String {
+        format!("{:x}", Sha256::digest(BODY))
+    }
+
+    /// Serves `BODY`, answering the first `failures` requests with a 503.
+    async fn serve(failures: usize) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 1024];
+                let read = socket.read(&mut request).await.unwrap();
+                assert!(read > 0);
+
+                let response = match counter.fetch_add(1, Ordering::SeqCst) < failures {
+                    true => b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_vec(),
+                    false => [
+                        format!(
+                            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                            BODY.len()
+                        )
+                        .as_bytes(),
+                        BODY,
+                    ]
+                    .concat(),
+                };
+                socket.write_all(&response).await.unwrap();
+            }
+        });
+
+        (format!("http://{}/tool", address), requests)
+    }
+
+    fn downloader() -> Downloader {
+        Downloader {
+            backoff: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn downloads_and_verifies() {
+        let (url, _) = serve(0).await;
+        let dir = tempfile::tempdir().unwrap();
+        let destination = dir.path().join("bin").join("tool");
+        let binary = RemoteBinary {
+            name: "tool".into(),
+            url,
+            sha256hash: body_checksum().to_ascii_uppercase(),
+        };
+
+        downloader().fetch(&binary, &destination).await.unwrap();
+
+        assert_eq!(std::fs::read(&destination).unwrap(), BODY);
+        assert!(!Downloader::partial_path(&destination).exists());
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let (url, requests) = serve(2).await;
+        let dir = tempfile::tempdir().unwrap();
+        let binary = RemoteBinary {
+            name: "tool".into(),
+            url,
+            sha256hash: body_checksum(),
+        };
+
+        downloader()
+            .fetch(&binary, &dir.path().join("tool"))
+            .await
+            .unwrap();
+
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_checksum_without_retrying() {
+        let (url, requests) = serve(0).await;
+        let dir = tempfile::tempdir().unwrap();
+        let destination = dir.path().join("tool");
+        let binary = RemoteBinary {
+            name: "tool".into(),
+            url,
+            sha256hash: "0".repeat(64),
+        };
+
+        let error = downloader().fetch(&binary, &destination).await.unwrap_err();
+
+        assert!(format!("{:#}", error).contains("`tool`"));
+        assert!(format!("{:#}", error).contains("checksum mismatch"));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(!destination.exists());
+    }
+
+    #[tokio::test]
+    async fn reuses_cached_downloads() {
+        let (url, requests) = serve(0).await;
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = Downloader {
+            cache: Some(BlobCache::new(dir.path().join("cache"))),
+            ..downloader()
+        };
+        let binary = RemoteBinary {
+            name: "tool".into(),
+            url,
+            sha256hash: body_checksum(),
+        };
+
+        for build in ["first", "second"] {
+            let destination = dir.path().join(build).join("tool");
+            downloader.fetch(&binary, &destination).await.unwrap();
+            assert_eq!(std::fs::read(&destination).unwrap(), BODY);
+        }
+
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn offline_uses_binaries_dir_only() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("tool"), BODY).unwrap();
+        let mut downloader = Downloader {
+            binaries_dir: Some(dir.path().to_path_buf()),
+            offline: true,
+            ..downloader()
+        };
+        let binary = RemoteBinary {
+            name: "tool".into(),
+            url: "http://127.0.0.1:9/unreachable".into(),
+            sha256hash: body_checksum(),
+        };
+
+        let destination = dir.path().join("bin").join("tool");
+        downloader.fetch(&binary, &destination).await.unwrap();
+        assert_eq!(std::fs::read(&destination).unwrap(), BODY);
+
+        downloader.binaries_dir = None;
+        let error = downloader.fetch(&binary, &destination).await.unwrap_err();
+        assert!(error.to_string().contains("network access is disabled"));
+    }
+
+    #[test]
+    fn names_invalid_entries() {
+        let error = RemoteBinaries::from_value(&json!({
+            "remote_binary_bundling": true,
+            "remote_binary": [
+                { "name": "ok", "url": "https://example.com", "sha256hash": body_checksum() },
+                { "name": "missing-url", "sha256hash": body_checksum() },
+            ]
+        }))
+        .unwrap_err();
+
+        assert!(format!("{:#}", error).contains("remote_binary[1]"));
+    }
+}